@@ -0,0 +1,61 @@
+//! Backends that carry out the operations of a [`Vhd`](crate::Vhd).
+
+use std::fmt::Debug;
+use std::io::{Read, Seek, Write};
+
+use crate::{DiskInfo, Error, ErrorKind, Result, VhdIdentifier, VhdMetadata};
+
+pub use native::NativeBackend;
+pub use simulated::{SimulatedBackend, SimulatedHost};
+#[cfg(windows)]
+pub use windows::VirtDiskBackend;
+
+mod native;
+mod simulated;
+#[cfg(windows)]
+mod windows;
+
+/// The operations a [`Vhd`](crate::Vhd) delegates to the engine that actually handles the
+/// virtual disk, such as the Windows virtdisk API or a pure-Rust file-format implementation.
+///
+/// A backend is opened for a single virtual disk and selected when the [`Vhd`](crate::Vhd) is
+/// created (see [`Vhd::from_backend`](crate::Vhd::from_backend)).
+pub trait VirtualDiskBackend: Debug {
+    /// Attaches the virtual disk to the host.
+    ///
+    /// If `persistent` is `false`, the attachment must end when the backend is dropped.
+    fn attach(&mut self, persistent: bool) -> Result<()>;
+
+    /// Detaches the virtual disk from the host.
+    fn detach(&mut self) -> Result<()>;
+
+    /// Returns the drive letters currently present on the host, in ascending order.
+    ///
+    /// [`Vhd::attach`](crate::Vhd::attach) compares the letters before and after attaching to
+    /// find the drive the virtual disk was mounted to.
+    fn drive_letters(&self) -> Vec<char>;
+
+    /// Retrieves the size information of the virtual disk.
+    fn get_size(&mut self) -> Result<DiskInfo>;
+
+    /// Retrieves the unique identifier of the virtual disk.
+    fn get_identifier(&mut self) -> Result<VhdIdentifier>;
+
+    /// Retrieves the descriptive metadata stored in the image. Backends that do not read the
+    /// image themselves return an [`ErrorKind::Unsupported`] error.
+    fn get_metadata(&mut self) -> Result<VhdMetadata> {
+        Err(Error::new(ErrorKind::Unsupported, "get metadata"))
+    }
+
+    /// Returns the contents of the virtual disk, if the backend can access them without
+    /// attaching the disk.
+    fn contents(&mut self) -> Option<&mut dyn DiskContents> {
+        None
+    }
+}
+
+/// The contents of a virtual disk as a byte stream, as exposed by
+/// [`VirtualDiskBackend::contents`].
+pub trait DiskContents: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> DiskContents for T {}
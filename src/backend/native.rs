@@ -0,0 +1,199 @@
+//! Backend built on the pure-Rust implementation of the file formats.
+
+use std::path::{Path, PathBuf};
+
+use crate::backend::{DiskContents, VirtualDiskBackend};
+use crate::vhd::VhdImage;
+use crate::vhdx::VhdxImage;
+use crate::{DiskInfo, Error, ErrorKind, OpenMode, Result, VhdIdentifier, VhdMetadata, VhdType};
+
+/// [`VirtualDiskBackend`] that parses the image file itself instead of going through the
+/// operating system, so it works on every platform.
+///
+/// It gives access to the contents of the virtual disk (see the [`Read`](std::io::Read) and
+/// [`Seek`](std::io::Seek) implementations of [`Vhd`](crate::Vhd)) but cannot attach it.
+#[derive(Debug)]
+pub struct NativeBackend {
+    image: Image,
+}
+
+/// The opened image, of either format.
+#[derive(Debug)]
+enum Image {
+    Vhd(VhdImage),
+    Vhdx(VhdxImage),
+}
+
+impl NativeBackend {
+    /// Opens a VHD/VHDX file. The VHD type is detected from the file content unless
+    /// `force_type` is explicitly specified.
+    ///
+    /// # Errors
+    /// If the file cannot be opened or parsed, or is of a type this backend does not support.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        open_mode: OpenMode,
+        force_type: Option<VhdType>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let vhd_type = match force_type {
+            Some(vhd_type) => vhd_type,
+            None => VhdType::detect(path)?,
+        };
+
+        let image = match vhd_type {
+            VhdType::Vhd => Image::Vhd(VhdImage::open(path, open_mode)?),
+            VhdType::Vhdx => Image::Vhdx(VhdxImage::open(path, open_mode)?),
+        };
+        Ok(NativeBackend { image })
+    }
+
+    /// Returns the opened image if it is a VHD.
+    pub fn image(&mut self) -> Option<&mut VhdImage> {
+        match &mut self.image {
+            Image::Vhd(image) => Some(image),
+            Image::Vhdx(_) => None,
+        }
+    }
+
+    /// Returns the opened image if it is a VHDX.
+    pub fn vhdx_image(&mut self) -> Option<&mut VhdxImage> {
+        match &mut self.image {
+            Image::Vhd(_) => None,
+            Image::Vhdx(image) => Some(image),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        match &self.image {
+            Image::Vhd(image) => image.path().to_path_buf(),
+            Image::Vhdx(image) => image.path().to_path_buf(),
+        }
+    }
+}
+
+/// Wraps an image that is already open, such as one just created.
+impl From<VhdImage> for NativeBackend {
+    fn from(image: VhdImage) -> Self {
+        NativeBackend {
+            image: Image::Vhd(image),
+        }
+    }
+}
+
+impl VirtualDiskBackend for NativeBackend {
+    fn attach(&mut self, _persistent: bool) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "attach")
+            .with_path(self.path())
+            .with_source("the native backend cannot attach virtual disks"))
+    }
+
+    fn detach(&mut self) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "detach")
+            .with_path(self.path())
+            .with_source("the native backend cannot detach virtual disks"))
+    }
+
+    fn drive_letters(&self) -> Vec<char> {
+        Vec::new()
+    }
+
+    fn get_size(&mut self) -> Result<DiskInfo> {
+        match &self.image {
+            Image::Vhd(image) => Ok(DiskInfo {
+                virtual_size: image.virtual_size(),
+                physical_size: image.physical_size()?,
+                block_size: image.block_size(),
+                sector_size: 512,
+            }),
+            Image::Vhdx(image) => Ok(DiskInfo {
+                virtual_size: image.virtual_size(),
+                physical_size: image.physical_size()?,
+                block_size: image.block_size(),
+                sector_size: image.metadata().logical_sector_size,
+            }),
+        }
+    }
+
+    fn get_identifier(&mut self) -> Result<VhdIdentifier> {
+        match &self.image {
+            Image::Vhd(image) => Ok(VhdIdentifier(image.footer().unique_id)),
+            Image::Vhdx(image) => Ok(VhdIdentifier(image.metadata().page_83_data)),
+        }
+    }
+
+    fn get_metadata(&mut self) -> Result<VhdMetadata> {
+        match &self.image {
+            Image::Vhd(image) => Ok(image.footer().metadata()),
+            Image::Vhdx(image) => Err(Error::new(ErrorKind::Unsupported, "get metadata")
+                .with_path(image.path())
+                .with_source("VHDX images have no VHD footer")),
+        }
+    }
+
+    fn contents(&mut self) -> Option<&mut dyn DiskContents> {
+        match &mut self.image {
+            Image::Vhd(image) => Some(image),
+            Image::Vhdx(image) => Some(image),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+
+    use crate::test_util::{fixed_vhd, vhdx_metadata, vhdx_with_blocks, TempFile};
+    use crate::{DiskType, ErrorKind, NativeBackend, OpenMode, Vhd};
+
+    #[test]
+    fn vhd_through_native_backend() {
+        let data: Vec<u8> = (0..2048u32).map(|i| (i / 7) as u8).collect();
+        let file = fixed_vhd(&data);
+        let backend = NativeBackend::open(file.path(), OpenMode::ReadOnly, None).unwrap();
+        let mut vhd = Vhd::from_backend(backend);
+
+        let info = vhd.get_size().unwrap();
+        assert_eq!(info.virtual_size, 2048);
+        assert_eq!(info.physical_size, 2048 + 512);
+        assert_eq!(info.sector_size, 512);
+        assert_eq!(vhd.get_identifier().unwrap().as_u128(), 1);
+        let metadata = vhd.get_metadata().unwrap();
+        assert_eq!(metadata.creator_application, "test");
+        assert_eq!(metadata.disk_type, DiskType::Fixed);
+
+        let mut buf = [0; 16];
+        vhd.seek(SeekFrom::Start(700)).unwrap();
+        vhd.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[700..716]);
+
+        assert_eq!(
+            vhd.attach(false).unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn vhdx_through_native_backend() {
+        let bytes = vhdx_with_blocks(&vhdx_metadata(8 << 20, 2 << 20), &[(1, vec![9; 512])]);
+        let file = TempFile::with_contents("native.vhdx", &bytes);
+        let backend = NativeBackend::open(file.path(), OpenMode::ReadOnly, None).unwrap();
+        let mut vhd = Vhd::from_backend(backend);
+
+        let info = vhd.get_size().unwrap();
+        assert_eq!(info.virtual_size, 8 << 20);
+        assert_eq!(info.physical_size, 6 << 20);
+        assert_eq!(info.block_size, 2 << 20);
+        assert_eq!(info.sector_size, 512);
+        assert_eq!(vhd.get_identifier().unwrap().as_u128(), 3);
+        assert_eq!(
+            vhd.get_metadata().unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+
+        let mut buf = [0; 4];
+        vhd.seek(SeekFrom::Start((2 << 20) + 510)).unwrap();
+        vhd.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [9, 9, 0, 0]);
+    }
+}
@@ -0,0 +1,322 @@
+//! In-memory backend that simulates how Windows assigns drive letters to attached disks.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::backend::VirtualDiskBackend;
+use crate::{DiskInfo, Error, ErrorKind, OpenMode, Result, VhdIdentifier};
+
+/// A simulated host with a drive-letter table that [`SimulatedBackend`]s attach to.
+///
+/// Cloning a [`SimulatedHost`] yields another handle to the same host, so a test can keep one
+/// to inspect the drive table while the backends opened from it are owned by [`Vhd`](crate::Vhd)s.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedHost {
+    state: Rc<RefCell<HostState>>,
+}
+
+#[derive(Debug, Default)]
+struct HostState {
+    /// Drive letter to the path of the disk that owns it, `None` for drives not backed by a
+    /// simulated disk.
+    drives: BTreeMap<char, Option<PathBuf>>,
+    attachments: HashMap<PathBuf, Attachment>,
+    volumes: HashMap<PathBuf, usize>,
+    /// Foreign drive letters that appear while the next attach is in progress.
+    pending: Vec<char>,
+    next_backend_id: u64,
+}
+
+#[derive(Debug)]
+struct Attachment {
+    persistent: bool,
+    owner: u64,
+}
+
+impl SimulatedHost {
+    /// Creates a host whose drive table only contains `letters`, none of which belong to a
+    /// simulated disk.
+    pub fn new(letters: &[char]) -> Self {
+        let host = SimulatedHost::default();
+        for &letter in letters {
+            host.add_drive(letter);
+        }
+        host
+    }
+
+    /// Adds a drive that does not belong to a simulated disk, as if another device was mounted.
+    pub fn add_drive(&self, letter: char) {
+        self.state.borrow_mut().drives.insert(letter, None);
+    }
+
+    /// Makes `letter` appear while the next attach is in progress, simulating another device
+    /// being mounted concurrently.
+    pub fn add_drive_during_next_attach(&self, letter: char) {
+        self.state.borrow_mut().pending.push(letter);
+    }
+
+    /// Sets the number of volumes that receive a drive letter when the disk at `path` is
+    /// attached. Disks have a single volume unless configured otherwise.
+    pub fn set_volumes<P: AsRef<Path>>(&self, path: P, volumes: usize) {
+        self.state
+            .borrow_mut()
+            .volumes
+            .insert(path.as_ref().to_path_buf(), volumes);
+    }
+
+    /// Returns the drive letters currently present on the host, in ascending order.
+    pub fn drive_letters(&self) -> Vec<char> {
+        self.state.borrow().drives.keys().copied().collect()
+    }
+
+    /// Returns the drive letters assigned to the disk at `path`, in ascending order.
+    pub fn drive_letters_of<P: AsRef<Path>>(&self, path: P) -> Vec<char> {
+        let path = path.as_ref();
+        self.state
+            .borrow()
+            .drives
+            .iter()
+            .filter(|(_, owner)| owner.as_deref() == Some(path))
+            .map(|(&letter, _)| letter)
+            .collect()
+    }
+
+    /// Returns `true` if the disk at `path` is attached.
+    pub fn is_attached<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.state.borrow().attachments.contains_key(path.as_ref())
+    }
+
+    /// Opens a simulated disk at `path`. The file does not need to exist.
+    pub fn open<P: AsRef<Path>>(&self, path: P, open_mode: OpenMode) -> SimulatedBackend {
+        let mut state = self.state.borrow_mut();
+        let id = state.next_backend_id;
+        state.next_backend_id += 1;
+
+        SimulatedBackend {
+            host: self.clone(),
+            path: path.as_ref().to_path_buf(),
+            mode: open_mode,
+            id,
+        }
+    }
+}
+
+impl HostState {
+    fn detach(&mut self, path: &Path) {
+        self.attachments.remove(path);
+        self.drives
+            .retain(|_, owner| owner.as_deref() != Some(path));
+    }
+}
+
+/// [`VirtualDiskBackend`] for a disk on a [`SimulatedHost`].
+///
+/// Like a virtdisk handle, dropping the backend detaches the disk if it attached it
+/// temporarily.
+#[derive(Debug)]
+pub struct SimulatedBackend {
+    host: SimulatedHost,
+    path: PathBuf,
+    mode: OpenMode,
+    id: u64,
+}
+
+impl SimulatedBackend {
+    /// Returns the mode the disk was opened in.
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+}
+
+impl Drop for SimulatedBackend {
+    fn drop(&mut self) {
+        let mut state = self.host.state.borrow_mut();
+        let owns_temporary = state
+            .attachments
+            .get(&self.path)
+            .is_some_and(|attachment| !attachment.persistent && attachment.owner == self.id);
+
+        if owns_temporary {
+            state.detach(&self.path);
+        }
+    }
+}
+
+impl VirtualDiskBackend for SimulatedBackend {
+    fn attach(&mut self, persistent: bool) -> Result<()> {
+        let mut state = self.host.state.borrow_mut();
+
+        if state.attachments.contains_key(&self.path) {
+            return Err(Error::new(ErrorKind::InUse, "attach").with_path(&self.path));
+        }
+
+        for letter in std::mem::take(&mut state.pending) {
+            state.drives.insert(letter, None);
+        }
+
+        let volumes = state.volumes.get(&self.path).copied().unwrap_or(1);
+        let free: Vec<char> = ('C'..='Z')
+            .filter(|letter| !state.drives.contains_key(letter))
+            .take(volumes)
+            .collect();
+        for letter in free {
+            state.drives.insert(letter, Some(self.path.clone()));
+        }
+
+        state.attachments.insert(
+            self.path.clone(),
+            Attachment {
+                persistent,
+                owner: self.id,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<()> {
+        let mut state = self.host.state.borrow_mut();
+
+        if !state.attachments.contains_key(&self.path) {
+            return Err(Error::new(ErrorKind::NotAttached, "detach").with_path(&self.path));
+        }
+
+        state.detach(&self.path);
+        Ok(())
+    }
+
+    fn drive_letters(&self) -> Vec<char> {
+        self.host.drive_letters()
+    }
+
+    fn get_size(&mut self) -> Result<DiskInfo> {
+        Err(Error::new(ErrorKind::Unsupported, "get size").with_path(&self.path))
+    }
+
+    fn get_identifier(&mut self) -> Result<VhdIdentifier> {
+        Err(Error::new(ErrorKind::Unsupported, "get identifier").with_path(&self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vhd;
+
+    #[test]
+    fn temporary_attach_ends_on_drop() {
+        let host = SimulatedHost::new(&['C']);
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+
+        let letter = vhd.attach(false).unwrap();
+        assert_eq!(letter, 'D');
+        assert_eq!(host.drive_letters(), ['C', 'D']);
+        assert!(host.is_attached("file.vhd"));
+
+        drop(vhd);
+        assert_eq!(host.drive_letters(), ['C']);
+        assert!(!host.is_attached("file.vhd"));
+    }
+
+    #[test]
+    fn persistent_attach_survives_drop() {
+        let host = SimulatedHost::new(&['C']);
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadWrite));
+
+        let letter = vhd.attach(true).unwrap();
+        drop(vhd);
+        assert_eq!(host.drive_letters_of("file.vhd"), [letter]);
+
+        host.open("file.vhd", OpenMode::ReadOnly).detach().unwrap();
+        assert_eq!(host.drive_letters(), ['C']);
+        assert!(!host.is_attached("file.vhd"));
+    }
+
+    #[test]
+    fn detach_without_attach() {
+        let host = SimulatedHost::new(&['C']);
+        let result = host.open("file.vhd", OpenMode::ReadOnly).detach();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotAttached);
+    }
+
+    #[test]
+    fn attach_twice() {
+        let host = SimulatedHost::new(&['C']);
+        let mut first = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+        let mut second = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+
+        first.attach(false).unwrap();
+        assert_eq!(second.attach(false).unwrap_err().kind(), ErrorKind::InUse);
+
+        // only the backend that attached the disk ends the attachment
+        drop(second);
+        assert!(host.is_attached("file.vhd"));
+        drop(first);
+        assert!(!host.is_attached("file.vhd"));
+    }
+
+    #[test]
+    fn skips_used_letters() {
+        let host = SimulatedHost::new(&['C', 'D', 'F']);
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+        assert_eq!(vhd.attach(false).unwrap(), 'E');
+    }
+
+    #[test]
+    fn mount_drive_detection_without_volumes() {
+        let host = SimulatedHost::new(&['C']);
+        host.set_volumes("file.vhd", 0);
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+
+        assert_eq!(
+            vhd.attach(false).unwrap_err().kind(),
+            ErrorKind::MountDriveDetection
+        );
+        // the disk is attached even though no drive letter could be detected
+        assert!(host.is_attached("file.vhd"));
+    }
+
+    #[test]
+    fn mount_drive_detection_without_free_letters() {
+        let letters: Vec<char> = ('A'..='Z').collect();
+        let host = SimulatedHost::new(&letters);
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+
+        assert_eq!(
+            vhd.attach(false).unwrap_err().kind(),
+            ErrorKind::MountDriveDetection
+        );
+    }
+
+    #[test]
+    fn multiple_volumes_report_lowest_letter() {
+        let host = SimulatedHost::new(&['C']);
+        host.set_volumes("file.vhd", 2);
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+
+        assert_eq!(vhd.attach(false).unwrap(), 'D');
+        assert_eq!(host.drive_letters_of("file.vhd"), ['D', 'E']);
+    }
+
+    #[test]
+    fn race_with_higher_foreign_letter() {
+        let host = SimulatedHost::new(&['C']);
+        host.add_drive_during_next_attach('X');
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+
+        assert_eq!(vhd.attach(false).unwrap(), 'D');
+    }
+
+    #[test]
+    fn race_with_lower_foreign_letter() {
+        let host = SimulatedHost::new(&['C']);
+        host.add_drive_during_next_attach('D');
+        let mut vhd = Vhd::from_backend(host.open("file.vhd", OpenMode::ReadOnly));
+
+        // the lowest new letter is reported, even though it belongs to the other device
+        assert_eq!(vhd.attach(false).unwrap(), 'D');
+        assert_eq!(host.drive_letters_of("file.vhd"), ['E']);
+    }
+}
@@ -0,0 +1,348 @@
+//! Windows backend built on the virtdisk API (`OpenVirtualDisk`, `AttachVirtualDisk`, ...).
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::raw::HANDLE;
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use uuid::Uuid;
+use windows_sys::core::GUID;
+use windows_sys::Win32::Foundation::{CloseHandle, ERROR_SUCCESS, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::Storage::FileSystem::GetLogicalDriveStringsW;
+use windows_sys::Win32::Storage::Vhd::{
+    AttachVirtualDisk, DetachVirtualDisk, GetVirtualDiskInformation, OpenVirtualDisk,
+    ATTACH_VIRTUAL_DISK_FLAG_PERMANENT_LIFETIME, ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY,
+    DETACH_VIRTUAL_DISK_FLAG_NONE, GET_VIRTUAL_DISK_INFO, GET_VIRTUAL_DISK_INFO_0,
+    GET_VIRTUAL_DISK_INFO_0_3, GET_VIRTUAL_DISK_INFO_IDENTIFIER, GET_VIRTUAL_DISK_INFO_SIZE,
+    VIRTUAL_DISK_ACCESS_ATTACH_RO, VIRTUAL_DISK_ACCESS_ATTACH_RW, VIRTUAL_DISK_ACCESS_DETACH,
+    VIRTUAL_DISK_ACCESS_GET_INFO, VIRTUAL_STORAGE_TYPE, VIRTUAL_STORAGE_TYPE_DEVICE_VHD,
+    VIRTUAL_STORAGE_TYPE_DEVICE_VHDX, VIRTUAL_STORAGE_TYPE_VENDOR_MICROSOFT,
+};
+
+use crate::backend::VirtualDiskBackend;
+use crate::{DiskInfo, Error, ErrorKind, OpenMode, Result, VhdIdentifier, VhdType};
+
+/// [`VirtualDiskBackend`] that drives the Windows virtdisk API.
+///
+/// Closing the handle (dropping the backend) ends any attachment that was not made persistent.
+#[derive(Debug)]
+pub struct VirtDiskBackend {
+    handle: HANDLE,
+    mode: OpenMode,
+    path: PathBuf,
+}
+
+impl Drop for VirtDiskBackend {
+    fn drop(&mut self) {
+        if !self.handle.is_null() && self.handle != INVALID_HANDLE_VALUE {
+            unsafe {
+                CloseHandle(self.handle);
+            }
+        }
+    }
+}
+
+impl From<GET_VIRTUAL_DISK_INFO_0_3> for DiskInfo {
+    fn from(value: GET_VIRTUAL_DISK_INFO_0_3) -> Self {
+        Self {
+            virtual_size: value.VirtualSize,
+            physical_size: value.PhysicalSize,
+            block_size: value.BlockSize,
+            sector_size: value.SectorSize,
+        }
+    }
+}
+
+impl From<GUID> for VhdIdentifier {
+    fn from(guid: GUID) -> Self {
+        let uuid = Uuid::from_fields(guid.data1, guid.data2, guid.data3, &guid.data4);
+        VhdIdentifier(uuid)
+    }
+}
+
+impl VirtDiskBackend {
+    /// Opens a VHD/VHDX file through `OpenVirtualDisk` with the access rights needed to attach
+    /// it and query its information. The VHD type is detected from the file content unless
+    /// `force_type` is explicitly specified.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be opened.
+    pub fn open<P: AsRef<OsStr>>(
+        path: P,
+        open_mode: OpenMode,
+        force_type: Option<VhdType>,
+    ) -> Result<Self> {
+        let mut access_flags = match open_mode {
+            OpenMode::ReadOnly => VIRTUAL_DISK_ACCESS_ATTACH_RO,
+            OpenMode::ReadWrite => VIRTUAL_DISK_ACCESS_ATTACH_RW,
+        };
+
+        access_flags |= VIRTUAL_DISK_ACCESS_GET_INFO;
+
+        Self::open_with_access(path, open_mode, force_type, access_flags)
+    }
+
+    /// Opens a VHD/VHDX file with only the access rights needed to detach it.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be opened.
+    pub fn open_for_detach<P: AsRef<OsStr>>(path: P) -> Result<Self> {
+        Self::open_with_access(path, OpenMode::ReadOnly, None, VIRTUAL_DISK_ACCESS_DETACH)
+    }
+
+    fn open_with_access<P: AsRef<OsStr>>(
+        path: P,
+        open_mode: OpenMode,
+        force_type: Option<VhdType>,
+        access_flags: i32,
+    ) -> Result<Self> {
+        let wide_path: Vec<u16> = path.as_ref().encode_wide().chain(Some(0)).collect();
+
+        let path = Path::new(&path);
+
+        let vhd_type = match force_type {
+            Some(vhd_type) => vhd_type,
+            None => match VhdType::detect(path) {
+                Ok(vhd_type) => vhd_type,
+                // the file may not be readable while it is attached
+                Err(e) if matches!(e.kind(), ErrorKind::InUse | ErrorKind::AccessDenied) => {
+                    VhdType::from_extension(path)?
+                }
+                Err(e) => return Err(e),
+            },
+        };
+
+        let mut handle = null_mut();
+
+        let storage_type = VIRTUAL_STORAGE_TYPE {
+            DeviceId: match vhd_type {
+                VhdType::Vhd => VIRTUAL_STORAGE_TYPE_DEVICE_VHD,
+                VhdType::Vhdx => VIRTUAL_STORAGE_TYPE_DEVICE_VHDX,
+            },
+            VendorId: VIRTUAL_STORAGE_TYPE_VENDOR_MICROSOFT,
+        };
+
+        let open_result = unsafe {
+            OpenVirtualDisk(
+                &storage_type,
+                wide_path.as_ptr(),
+                access_flags,
+                0,
+                std::ptr::null(),
+                &mut handle,
+            )
+        };
+
+        if open_result != ERROR_SUCCESS {
+            return Err(Error::os("open", open_result).with_path(path));
+        };
+
+        Ok(VirtDiskBackend {
+            handle,
+            mode: open_mode,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl VirtualDiskBackend for VirtDiskBackend {
+    fn attach(&mut self, persistent: bool) -> Result<()> {
+        let mut flags = 0;
+
+        if matches!(self.mode, OpenMode::ReadOnly) {
+            flags |= ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY;
+        }
+
+        if persistent {
+            flags |= ATTACH_VIRTUAL_DISK_FLAG_PERMANENT_LIFETIME;
+        }
+
+        let attach_result = unsafe {
+            AttachVirtualDisk(
+                self.handle,
+                std::ptr::null_mut(),
+                flags,
+                0,
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+
+        if attach_result != ERROR_SUCCESS {
+            return Err(Error::os("attach", attach_result).with_path(&self.path));
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<()> {
+        let result = unsafe { DetachVirtualDisk(self.handle, DETACH_VIRTUAL_DISK_FLAG_NONE, 0) };
+        if result != ERROR_SUCCESS {
+            return Err(Error::os("detach", result).with_path(&self.path));
+        }
+        Ok(())
+    }
+
+    fn drive_letters(&self) -> Vec<char> {
+        // NOTE: 512 is more than enough
+        let mut buffer: [u16; 512] = [0; 512];
+        let buffer_len =
+            unsafe { GetLogicalDriveStringsW(buffer.len() as u32, buffer.as_mut_ptr()) };
+
+        // Pre-allocate vector for drive letters (estimate 1 char per 4 wide chars)
+        let mut drive_letters = Vec::with_capacity((buffer_len / 4) as usize);
+
+        let mut start = 0;
+        for i in 0..buffer_len as usize {
+            if buffer[i] == 0 {
+                if start < i {
+                    // Get the first character of the wide string slice and convert it to char
+                    if let Some(first_char) = std::char::from_u32(u32::from(buffer[start])) {
+                        drive_letters.push(first_char);
+                    }
+                }
+                start = i + 1;
+            }
+        }
+
+        drive_letters
+    }
+
+    fn get_size(&mut self) -> Result<DiskInfo> {
+        let mut info = GET_VIRTUAL_DISK_INFO {
+            Version: GET_VIRTUAL_DISK_INFO_SIZE,
+            Anonymous: GET_VIRTUAL_DISK_INFO_0 {
+                Size: GET_VIRTUAL_DISK_INFO_0_3 {
+                    VirtualSize: 0,
+                    PhysicalSize: 0,
+                    BlockSize: 0,
+                    SectorSize: 0,
+                },
+            },
+        };
+
+        let mut info_size = std::mem::size_of::<GET_VIRTUAL_DISK_INFO>() as u32;
+
+        let result = unsafe {
+            GetVirtualDiskInformation(self.handle, &mut info_size, &mut info, std::ptr::null_mut())
+        };
+
+        if result != ERROR_SUCCESS {
+            return Err(Error::os("get size", result).with_path(&self.path));
+        }
+
+        unsafe { Ok(info.Anonymous.Size.into()) }
+    }
+
+    fn get_identifier(&mut self) -> Result<VhdIdentifier> {
+        let mut info = GET_VIRTUAL_DISK_INFO {
+            Version: GET_VIRTUAL_DISK_INFO_IDENTIFIER,
+            Anonymous: GET_VIRTUAL_DISK_INFO_0 {
+                Identifier: GUID {
+                    data1: 0,
+                    data2: 0,
+                    data3: 0,
+                    data4: [0; 8],
+                },
+            },
+        };
+
+        let mut info_size = std::mem::size_of::<GET_VIRTUAL_DISK_INFO>() as u32;
+
+        let result = unsafe {
+            GetVirtualDiskInformation(self.handle, &mut info_size, &mut info, std::ptr::null_mut())
+        };
+
+        if result != ERROR_SUCCESS {
+            return Err(Error::os("get identifier", result).with_path(&self.path));
+        }
+
+        let identifier: VhdIdentifier = unsafe { info.Anonymous.Identifier.into() };
+        Ok(identifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{OpenMode, Vhd};
+    use std::path::Path;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    // NOTE: Adding an initial sleep to ensure Windows has enough time to stabilize before running
+    // the tests, as they execute too quickly, even in single-threaded mode.
+
+    #[test]
+    fn get_size() {
+        sleep(Duration::from_secs(1));
+        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
+        let info = vhd.get_size();
+        dbg!(&info);
+    }
+
+    #[test]
+    fn get_identifier() {
+        sleep(Duration::from_secs(1));
+        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
+        let info = vhd.get_identifier().unwrap();
+        dbg!(&info);
+    }
+
+    #[test]
+    fn mount_vhd_read_only_temporary() {
+        sleep(Duration::from_secs(1));
+        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
+        let letter = vhd.attach(false).unwrap();
+        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
+        drop(vhd);
+        assert!(!Path::new(&format!(r"{letter}:\")).is_dir());
+    }
+
+    #[test]
+    fn mount_vhd_read_write_temporary() {
+        sleep(Duration::from_secs(1));
+        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadWrite, None).unwrap();
+        let letter = vhd.attach(false).unwrap();
+        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
+        drop(vhd);
+        assert!(!Path::new(&format!(r"{letter}:\")).is_dir());
+    }
+
+    #[test]
+    fn mount_vhd_read_only_permanent() {
+        sleep(Duration::from_secs(1));
+        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
+        let letter = vhd.attach(true).unwrap();
+        drop(vhd);
+        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
+        Vhd::detach("file.vhd").unwrap();
+    }
+
+    #[test]
+    fn mount_vhd_read_write_permanent() {
+        sleep(Duration::from_secs(1));
+        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadWrite, None).unwrap();
+        let letter = vhd.attach(true).unwrap();
+        drop(vhd);
+        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
+        Vhd::detach("file.vhd").unwrap();
+    }
+
+    // for manual testing
+    #[ignore]
+    #[test]
+    fn attach() {
+        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
+        let attach_result = vhd.attach(true);
+        dbg!(&attach_result);
+        assert!(attach_result.is_ok());
+    }
+
+    // for manual testing
+    #[ignore]
+    #[test]
+    fn detach() {
+        let result = Vhd::detach("file.vhd");
+        dbg!(&result);
+        assert!(result.is_ok());
+    }
+}
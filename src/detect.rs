@@ -0,0 +1,212 @@
+//! Detection of the image format from the content of a file.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::{Error, ErrorKind, Result, VhdType};
+
+/// Cookie of the VHD footer, found at the end of every VHD and at offset 0 of dynamic and
+/// differencing VHDs.
+const VHD_COOKIE: &[u8] = b"conectix";
+/// Signature of the VHDX file type identifier at offset 0.
+const VHDX_SIGNATURE: &[u8] = b"vhdxfile";
+/// Signature of the two VHDX headers, and their offsets.
+const VHDX_HEADER_SIGNATURE: &[u8; 4] = b"head";
+const VHDX_HEADER_OFFSETS: [u64; 2] = [64 << 10, 128 << 10];
+
+/// Formats that are recognized but cannot be opened, with the signature at offset 0 that
+/// identifies them.
+const FOREIGN_FORMATS: &[(&[u8], &str)] = &[
+    (b"QFI\xfb", "qcow2"),
+    (b"QED\x00", "QED"),
+    (b"KDMV", "VMDK"),
+    (b"COWD", "VMDK (ESX sparse)"),
+    (b"# Disk DescriptorFile", "VMDK (descriptor)"),
+    (b"<<< Oracle VM VirtualBox Disk Image >>>", "VDI"),
+    (b"<<< Sun VirtualBox Disk Image >>>", "VDI"),
+    (b"<<< Sun xVM VirtualBox Disk Image >>>", "VDI"),
+    (b"<<< innotek VirtualBox Disk Image >>>", "VDI"),
+    (b"WithoutFreeSpace", "Parallels"),
+    (b"WithouFreSpacExt", "Parallels"),
+];
+
+/// Offset and little-endian value of the VDI signature, for VDI files whose text header was
+/// altered.
+const VDI_SIGNATURE_OFFSET: usize = 64;
+const VDI_SIGNATURE: u32 = 0xbeda_107f;
+
+impl VhdType {
+    /// Detects the [`VhdType`] of the file at `path` from its content rather than its extension.
+    ///
+    /// VHDX files are recognized by the `vhdxfile` identifier at offset 0 followed by a header
+    /// signature at 64 or 128 KiB, VHD files by the `conectix` cookie of the footer at the end of
+    /// the file (or of the footer copy at offset 0 that dynamic and differencing VHDs carry). As
+    /// the guest data of a fixed VHD starts at offset 0, a file with the `vhdxfile` identifier but
+    /// neither header signature is only taken for a VHDX if it has no VHD footer either.
+    ///
+    /// # Errors
+    /// Returns an [`ErrorKind::Unsupported`] error naming the format if the file is a qcow2,
+    /// VMDK, VDI or other known image format, or if no signature was found.
+    pub fn detect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(|e| Error::io("detect", e).with_path(path))?;
+        Self::detect_from(&mut file).map_err(|e| e.with_path(path))
+    }
+
+    /// Detects the [`VhdType`] of the image in `reader`. See [`VhdType::detect`].
+    ///
+    /// # Errors
+    /// Returns an [`ErrorKind::Unsupported`] error if the image is not a VHD or VHDX.
+    pub fn detect_from<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let io_error = |e| Error::io("detect", e);
+
+        let len = reader.seek(SeekFrom::End(0)).map_err(io_error)?;
+
+        let mut head = vec![0; len.min(512) as usize];
+        reader.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        reader.read_exact(&mut head).map_err(io_error)?;
+
+        let vhdx_identifier = head.starts_with(VHDX_SIGNATURE);
+        if vhdx_identifier {
+            for offset in VHDX_HEADER_OFFSETS {
+                if offset + VHDX_HEADER_SIGNATURE.len() as u64 > len {
+                    break;
+                }
+                let mut signature = [0; 4];
+                reader.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+                reader.read_exact(&mut signature).map_err(io_error)?;
+                if &signature == VHDX_HEADER_SIGNATURE {
+                    return Ok(VhdType::Vhdx);
+                }
+            }
+        }
+        if head.starts_with(VHD_COOKIE) {
+            return Ok(VhdType::Vhd);
+        }
+
+        if len >= 512 {
+            let mut tail = [0; 512];
+            reader.seek(SeekFrom::End(-512)).map_err(io_error)?;
+            reader.read_exact(&mut tail).map_err(io_error)?;
+
+            // images created before Virtual PC 2004 have a 511-byte footer
+            if tail.starts_with(VHD_COOKIE) || tail[1..].starts_with(VHD_COOKIE) {
+                return Ok(VhdType::Vhd);
+            }
+        }
+
+        // a VHDX whose headers are both damaged, which opening it reports
+        if vhdx_identifier {
+            return Ok(VhdType::Vhdx);
+        }
+
+        let unsupported = Error::new(ErrorKind::Unsupported, "detect");
+
+        if let Some((_, name)) = FOREIGN_FORMATS
+            .iter()
+            .find(|(signature, _)| head.starts_with(signature))
+        {
+            return Err(unsupported.with_source(format!("{name} images are not supported")));
+        }
+
+        let vdi_signature = head
+            .get(VDI_SIGNATURE_OFFSET..VDI_SIGNATURE_OFFSET + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+        if vdi_signature == Some(VDI_SIGNATURE) {
+            return Err(unsupported.with_source("VDI images are not supported"));
+        }
+
+        Err(unsupported.with_source("no VHD or VHDX signature found"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn detect(bytes: Vec<u8>) -> Result<VhdType> {
+        VhdType::detect_from(&mut Cursor::new(bytes))
+    }
+
+    fn image(head: &[u8], tail: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; 4096];
+        bytes[..head.len()].copy_from_slice(head);
+        let tail_start = bytes.len() - 512;
+        bytes[tail_start..tail_start + tail.len()].copy_from_slice(tail);
+        bytes
+    }
+
+    #[test]
+    fn vhdx() {
+        assert_eq!(detect(image(b"vhdxfile", b"")).unwrap(), VhdType::Vhdx);
+    }
+
+    #[test]
+    fn vhdx_with_headers() {
+        let mut bytes = image(b"vhdxfile", b"");
+        bytes.resize(192 << 10, 0);
+        bytes[128 << 10..(128 << 10) + 4].copy_from_slice(b"head");
+        let tail_start = bytes.len() - 512;
+        bytes[tail_start..tail_start + 8].copy_from_slice(b"conectix");
+        assert_eq!(detect(bytes).unwrap(), VhdType::Vhdx);
+    }
+
+    #[test]
+    fn fixed_vhd_with_vhdx_identifier_in_data() {
+        let bytes = image(b"vhdxfile", b"conectix");
+        assert_eq!(detect(bytes).unwrap(), VhdType::Vhd);
+    }
+
+    #[test]
+    fn fixed_vhd() {
+        assert_eq!(detect(image(b"", b"conectix")).unwrap(), VhdType::Vhd);
+    }
+
+    #[test]
+    fn dynamic_vhd_with_truncated_footer() {
+        let mut bytes = image(b"conectix", b"");
+        bytes.truncate(1536);
+        assert_eq!(detect(bytes).unwrap(), VhdType::Vhd);
+    }
+
+    #[test]
+    fn vhd_with_511_byte_footer() {
+        let mut bytes = image(b"", b"");
+        let len = bytes.len();
+        bytes[len - 511..len - 503].copy_from_slice(b"conectix");
+        assert_eq!(detect(bytes).unwrap(), VhdType::Vhd);
+    }
+
+    #[test]
+    fn foreign_formats() {
+        for (head, name) in [
+            (&b"QFI\xfb\x00\x00\x00\x03"[..], "qcow2"),
+            (b"KDMV", "VMDK"),
+            (b"# Disk DescriptorFile\n", "VMDK (descriptor)"),
+            (b"<<< Oracle VM VirtualBox Disk Image >>>\n", "VDI"),
+        ] {
+            let error = detect(image(head, b"")).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::Unsupported);
+            assert!(error.to_string().contains(name), "{error}");
+        }
+    }
+
+    #[test]
+    fn vdi_by_binary_signature() {
+        let mut bytes = image(b"", b"");
+        bytes[64..68].copy_from_slice(&VDI_SIGNATURE.to_le_bytes());
+        let error = detect(bytes).unwrap_err();
+        assert!(error.to_string().contains("VDI"), "{error}");
+    }
+
+    #[test]
+    fn unknown_content() {
+        let error = detect(image(b"", b"")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+        let error = detect(Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+}
@@ -1,15 +1,21 @@
 /*!
 A lightweight library that provides an ergonomic interface for managing Virtual Hard Disks (VHD/VHDX) on Windows systems. It leverages the Windows API to facilitate operations such as opening, attaching, detaching, and retrieving information from VHD files.
 
+Every operation of a [`Vhd`] is delegated to a [`VirtualDiskBackend`]. The Windows API backend (`VirtDiskBackend`) is only compiled on Windows; the rest of the crate builds on every platform. [`SimulatedHost`] provides an in-memory backend for testing mount logic without Windows.
+
 # Features
 - Open VHD/VHDX Files: Supports opening VHD/VHDX files in both `ReadOnly` and `ReadWrite` modes.
 - Mounting and Unmounting: Attach and detach virtual disks to and from the system with options for persistent and temporary mounts.
 - Disk Information Retrieval: Obtain detailed information about the virtual disk, including its size and unique identifier.
+- Reading Without Attaching: Read and write the contents of fixed, dynamic and differencing VHD files, and of fixed and dynamic VHDX files, in pure Rust, on any platform.
+- Creating Images: Create fixed, dynamic and differencing VHD files in pure Rust, on any platform.
+- Footer Repair: Restore the trailing footer of a dynamic or differencing VHD from the copy at offset 0 after a crash.
+- VHDX Inspection: Read the headers, region table and metadata of VHDX files, validated with CRC-32C, and get their size and identifier through the `NativeBackend`, in pure Rust.
 - Automatic Resource Management: Handles cleanup operations, ensuring that resources like file handles are correctly released.
 
 # Usage
 ## Opening a VHD/VHDX File
-You can open a VHD/VHDX file by specifying the file path and the desired access mode. The file type is inferred from the extension unless explicitly specified.
+You can open a VHD/VHDX file by specifying the file path and the desired access mode. The file type is detected from the file content unless explicitly specified.
 
 ```no_run
 let vhd = vhdrs::Vhd::new("file.vhd", vhdrs::OpenMode::ReadOnly, None).unwrap();
@@ -48,46 +54,103 @@ let mut vhd = vhdrs::Vhd::new("file.vhd", vhdrs::OpenMode::ReadOnly, None).unwra
 let identifier = vhd.get_identifier().unwrap();
 println!("VHD Identifier: {}", identifier);
 ```
+
+## Reading the Image Metadata
+The [`NativeBackend`] also decodes the descriptive fields of the VHD footer: the application, version and host OS that created the image, when it was last written and whether it is in a saved state.
+
+```no_run
+let backend = vhdrs::NativeBackend::open("file.vhd", vhdrs::OpenMode::ReadOnly, None).unwrap();
+let mut vhd = vhdrs::Vhd::from_backend(backend);
+let metadata = vhd.get_metadata().unwrap();
+println!("Created by {} {} on {}", metadata.creator_application, metadata.creator_version, metadata.creator_host_os);
+```
+
+## Reading the Disk Contents
+The [`NativeBackend`] parses the image itself, so the virtual disk can be read through [`std::io::Read`] and [`std::io::Seek`], and written through [`std::io::Write`] when opened in `ReadWrite` mode, without attaching it, on any platform. VHDX images are read with their logical sector size, blocks that are not present read as zeros, and writing to them allocates 1 MiB-aligned blocks whose BAT entries are updated through the log; differencing VHDX images are not supported yet.
+
+```no_run
+use std::io::Read;
+
+let backend = vhdrs::NativeBackend::open("file.vhd", vhdrs::OpenMode::ReadOnly, None).unwrap();
+let mut vhd = vhdrs::Vhd::from_backend(backend);
+let mut boot_sector = [0; 512];
+vhd.read_exact(&mut boot_sector).unwrap();
+```
+
+## Creating a VHD
+[`Vhd::create_fixed`] writes a new fixed VHD and returns it opened for reading and writing with the [`NativeBackend`]. [`CreateOptions`] selects whether the host file is sparse or preallocated.
+
+```no_run
+use std::io::Write;
+
+let mut vhd = vhdrs::Vhd::create_fixed("new.vhd", 64 * 1024 * 1024).unwrap();
+vhd.write_all(b"hello").unwrap();
+
+let options = vhdrs::CreateOptions::new().allocation(vhdrs::Allocation::Preallocate);
+let vhd = vhdrs::Vhd::create_fixed_with("preallocated.vhd", 64 * 1024 * 1024, &options).unwrap();
+```
+
+[`Vhd::create_dynamic`] writes a thin-provisioned image whose blocks are allocated on demand. The block size (512 KiB to 2 MiB) and the alignment of the BAT are configurable.
+
+```no_run
+let options = vhdrs::CreateOptions::new()
+    .block_size(512 * 1024)
+    .bat_alignment(1024 * 1024);
+let vhd = vhdrs::Vhd::create_dynamic_with("dynamic.vhd", 1024 * 1024 * 1024, &options).unwrap();
+```
+
+[`Vhd::create_differencing`] writes a child of an existing VHD. Unwritten sectors of the child are read from the parent, which is found again through relative and absolute parent locators.
+
+```no_run
+let child = vhdrs::Vhd::create_differencing("child.vhd", "base.vhd").unwrap();
+```
+
+## Handling Errors
+Every error carries an [`ErrorKind`], the operation and path that failed, and the underlying OS error if there is one.
+
+```no_run
+match vhdrs::Vhd::new("file.vhd", vhdrs::OpenMode::ReadOnly, None) {
+    Ok(vhd) => println!("Opened: {:?}", vhd),
+    Err(e) if e.kind() == vhdrs::ErrorKind::NotFound => println!("No such file"),
+    Err(e) => println!("Error: {}", e),
+}
+```
 */
 
 use std::ffi::OsStr;
 use std::fmt::Display;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::ops::Deref;
-use std::os::windows::ffi::OsStrExt;
-use std::os::windows::raw::HANDLE;
-use std::ptr::null_mut;
+use std::path::Path;
 use uuid::Uuid;
-use windows_sys::core::GUID;
-use windows_sys::Win32::Foundation::{CloseHandle, ERROR_SUCCESS, INVALID_HANDLE_VALUE};
-use windows_sys::Win32::Storage::FileSystem::GetLogicalDriveStringsW;
-use windows_sys::Win32::Storage::Vhd::{
-    AttachVirtualDisk, DetachVirtualDisk, GetVirtualDiskInformation, OpenVirtualDisk,
-    ATTACH_VIRTUAL_DISK_FLAG_PERMANENT_LIFETIME, ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY,
-    DETACH_VIRTUAL_DISK_FLAG_NONE, GET_VIRTUAL_DISK_INFO, GET_VIRTUAL_DISK_INFO_0,
-    GET_VIRTUAL_DISK_INFO_0_3, GET_VIRTUAL_DISK_INFO_IDENTIFIER, GET_VIRTUAL_DISK_INFO_SIZE,
-    VIRTUAL_DISK_ACCESS_ATTACH_RO, VIRTUAL_DISK_ACCESS_ATTACH_RW, VIRTUAL_DISK_ACCESS_DETACH,
-    VIRTUAL_DISK_ACCESS_GET_INFO, VIRTUAL_STORAGE_TYPE, VIRTUAL_STORAGE_TYPE_DEVICE_VHD,
-    VIRTUAL_STORAGE_TYPE_DEVICE_VHDX, VIRTUAL_STORAGE_TYPE_VENDOR_MICROSOFT,
-};
 
-pub use error::{Error, Result};
+#[cfg(windows)]
+pub use backend::VirtDiskBackend;
+pub use backend::{
+    DiskContents, NativeBackend, SimulatedBackend, SimulatedHost, VirtualDiskBackend,
+};
+pub use error::{Error, ErrorKind, ParseError, Result, Structure};
+pub use vhd::{
+    Allocation, CreateOptions, DiskGeometry, DiskType, DynamicHeader, FooterRepair, HostOs,
+    ParentLocator, PlatformCode, Version, VhdFooter, VhdImage, VhdMetadata,
+};
+pub use vhdx::{
+    FileIdentifier, FileParameters, MetadataTable, MetadataTableEntry, PayloadBlock, PayloadState,
+    RegionTable, RegionTableEntry, SectorBitmapBlock, VhdxBat, VhdxHeader, VhdxImage, VhdxMetadata,
+    VhdxParentLocator,
+};
 
+mod backend;
+mod detect;
 mod error;
+#[cfg(test)]
+mod test_util;
+mod vhd;
+mod vhdx;
 
 #[derive(Debug)]
 pub struct Vhd {
-    handle: HANDLE,
-    mode: OpenMode,
-}
-
-impl Drop for Vhd {
-    fn drop(&mut self) {
-        if !self.handle.is_null() && self.handle != INVALID_HANDLE_VALUE {
-            unsafe {
-                CloseHandle(self.handle);
-            }
-        }
-    }
+    backend: Box<dyn VirtualDiskBackend>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -98,29 +161,40 @@ pub struct DiskInfo {
     pub sector_size: u32,
 }
 
-impl From<GET_VIRTUAL_DISK_INFO_0_3> for DiskInfo {
-    fn from(value: GET_VIRTUAL_DISK_INFO_0_3) -> Self {
-        Self {
-            virtual_size: value.VirtualSize,
-            physical_size: value.PhysicalSize,
-            block_size: value.BlockSize,
-            sector_size: value.SectorSize,
-        }
-    }
-}
-
 #[derive(Debug, Copy, Clone)]
 pub enum OpenMode {
     ReadOnly,
     ReadWrite,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VhdType {
     Vhd,
     Vhdx,
 }
 
+impl VhdType {
+    /// Infers the [`VhdType`] from the extension of `path` (`.vhd` or `.vhdx`, case-insensitive).
+    ///
+    /// # Errors
+    /// Returns an [`ErrorKind::InvalidInput`] error for any other extension.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn from_extension(path: &Path) -> Result<Self> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+
+        match ext.as_deref() {
+            Some("vhd") => Ok(VhdType::Vhd),
+            Some("vhdx") => Ok(VhdType::Vhdx),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "open")
+                .with_path(path)
+                .with_source("cannot infer the virtual disk type from the file extension")),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct VhdIdentifier(Uuid);
 
@@ -138,22 +212,18 @@ impl Display for VhdIdentifier {
     }
 }
 
-impl From<GUID> for VhdIdentifier {
-    fn from(guid: GUID) -> Self {
-        let uuid = Uuid::from_fields(guid.data1, guid.data2, guid.data3, &guid.data4);
-        VhdIdentifier(uuid)
-    }
-}
-
 impl Vhd {
     /// Opens a VHD/VHDX file in either `ReadOnly` or `ReadWrite` mode. This method does not
-    /// automatically attach the file. The VHD type is inferred from the file extension unless
-    /// `force_type` is explicitly specified.
+    /// automatically attach the file. The VHD type is detected from the file content (see
+    /// [`VhdType::detect`]) unless `force_type` is explicitly specified.
+    ///
+    /// On Windows the file is opened with the `VirtDiskBackend`, elsewhere with the
+    /// [`NativeBackend`]. Use [`Vhd::from_backend`] to select a different backend.
     ///
     /// # Parameters
     /// - `path`: The path to the VHD/VHDX file.
     /// - `open_mode`: Specifies the mode in which to open the file (`ReadOnly` or `ReadWrite`).
-    /// - `force_type`: An optional parameter to explicitly set the VHD type, overriding the inferred type.
+    /// - `force_type`: An optional parameter to explicitly set the VHD type, overriding the detected type.
     ///
     /// # Errors
     /// Returns an error if the file could not be opened.
@@ -162,68 +232,113 @@ impl Vhd {
         open_mode: OpenMode,
         force_type: Option<VhdType>,
     ) -> Result<Self> {
-        let mut access_flags = match open_mode {
-            OpenMode::ReadOnly => VIRTUAL_DISK_ACCESS_ATTACH_RO,
-            OpenMode::ReadWrite => VIRTUAL_DISK_ACCESS_ATTACH_RW,
-        };
+        #[cfg(windows)]
+        {
+            let backend = VirtDiskBackend::open(path, open_mode, force_type)?;
+            Ok(Self::from_backend(backend))
+        }
 
-        access_flags |= VIRTUAL_DISK_ACCESS_GET_INFO;
+        #[cfg(not(windows))]
+        {
+            let backend = NativeBackend::open(path.as_ref(), open_mode, force_type)?;
+            Ok(Self::from_backend(backend))
+        }
+    }
 
-        Self::open(path, open_mode, force_type, access_flags)
+    /// Creates a fixed VHD of `size` bytes at `path` with the default [`CreateOptions`] and
+    /// opens it in `ReadWrite` mode with the [`NativeBackend`], ready for reading and writing.
+    ///
+    /// # Errors
+    /// See [`Vhd::create_fixed_with`].
+    pub fn create_fixed<P: AsRef<Path>>(path: P, size: u64) -> Result<Self> {
+        Self::create_fixed_with(path, size, &CreateOptions::new())
     }
 
-    fn open<P: AsRef<OsStr>>(
+    /// Creates a fixed VHD of `size` bytes at `path` with the given [`CreateOptions`] and
+    /// opens it in `ReadWrite` mode with the [`NativeBackend`].
+    ///
+    /// The footer is written as the specification requires: geometry computed from `size`, this
+    /// crate as the creator application, a random unique ID and a valid checksum.
+    ///
+    /// # Errors
+    /// An [`ErrorKind::InvalidInput`] error if `size` is zero, not a multiple of 512 or larger
+    /// than 2040 GiB, an [`ErrorKind::AlreadyExists`] error if the file exists, or an error if
+    /// the file cannot be written.
+    pub fn create_fixed_with<P: AsRef<Path>>(
         path: P,
-        open_mode: OpenMode,
-        force_type: Option<VhdType>,
-        access_flags: i32,
+        size: u64,
+        options: &CreateOptions,
+    ) -> Result<Self> {
+        let image = VhdImage::create_fixed(path, size, options)?;
+        Ok(Self::from_backend(NativeBackend::from(image)))
+    }
+
+    /// Creates a dynamic VHD of `size` bytes at `path` with the default [`CreateOptions`]
+    /// (2 MiB blocks) and opens it in `ReadWrite` mode with the [`NativeBackend`].
+    ///
+    /// # Errors
+    /// See [`Vhd::create_dynamic_with`].
+    pub fn create_dynamic<P: AsRef<Path>>(path: P, size: u64) -> Result<Self> {
+        Self::create_dynamic_with(path, size, &CreateOptions::new())
+    }
+
+    /// Creates a dynamic VHD of `size` bytes at `path` with the block size and BAT alignment of
+    /// the given [`CreateOptions`] and opens it in `ReadWrite` mode with the [`NativeBackend`].
+    ///
+    /// The file only holds the footer, its copy at offset 0, the dynamic disk header and the
+    /// BAT; no block is allocated yet.
+    ///
+    /// # Errors
+    /// An [`ErrorKind::InvalidInput`] error if `size` is zero, not a multiple of 512 or larger
+    /// than 2040 GiB or if the options are invalid, an [`ErrorKind::AlreadyExists`] error if the
+    /// file exists, or an error if the file cannot be written.
+    pub fn create_dynamic_with<P: AsRef<Path>>(
+        path: P,
+        size: u64,
+        options: &CreateOptions,
+    ) -> Result<Self> {
+        let image = VhdImage::create_dynamic(path, size, options)?;
+        Ok(Self::from_backend(NativeBackend::from(image)))
+    }
+
+    /// Creates a differencing VHD at `path` over the VHD at `parent_path` with the default
+    /// [`CreateOptions`] and opens it in `ReadWrite` mode with the [`NativeBackend`].
+    ///
+    /// # Errors
+    /// See [`Vhd::create_differencing_with`].
+    pub fn create_differencing<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        parent_path: Q,
+    ) -> Result<Self> {
+        Self::create_differencing_with(path, parent_path, &CreateOptions::new())
+    }
+
+    /// Creates a differencing VHD at `path` over the VHD at `parent_path` with the block size
+    /// and BAT alignment of the given [`CreateOptions`] and opens it in `ReadWrite` mode with
+    /// the [`NativeBackend`].
+    ///
+    /// The child records the unique ID, modification time and file name of the parent, and
+    /// locates it with both a relative (`W2ru`) and an absolute (`W2ku`) parent locator. Until
+    /// it is written to, the child reads like its parent.
+    ///
+    /// # Errors
+    /// If the parent cannot be opened, an [`ErrorKind::InvalidInput`] error if the options are
+    /// invalid, an [`ErrorKind::AlreadyExists`] error if the file exists, or an error if the
+    /// file cannot be written.
+    pub fn create_differencing_with<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        parent_path: Q,
+        options: &CreateOptions,
     ) -> Result<Self> {
-        let wide_path: Vec<u16> = path.as_ref().encode_wide().chain(Some(0)).collect();
-
-        let vhd_type = if let Some(vhd_type) = force_type {
-            Ok(vhd_type)
-        } else {
-            let ext = std::path::Path::new(&path)
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .map(str::to_lowercase);
-
-            match ext.as_deref() {
-                Some("vhd") => Ok(VhdType::Vhd),
-                Some("vhdx") => Ok(VhdType::Vhdx),
-                _ => Err(Error::UnknownFileExtension),
-            }
-        }?;
-
-        let mut handle = null_mut();
-
-        let storage_type = VIRTUAL_STORAGE_TYPE {
-            DeviceId: match vhd_type {
-                VhdType::Vhd => VIRTUAL_STORAGE_TYPE_DEVICE_VHD,
-                VhdType::Vhdx => VIRTUAL_STORAGE_TYPE_DEVICE_VHDX,
-            },
-            VendorId: VIRTUAL_STORAGE_TYPE_VENDOR_MICROSOFT,
-        };
-
-        let open_result = unsafe {
-            OpenVirtualDisk(
-                &storage_type,
-                wide_path.as_ptr(),
-                access_flags,
-                0,
-                std::ptr::null(),
-                &mut handle,
-            )
-        };
-
-        if open_result != ERROR_SUCCESS {
-            return Err(open_result.into());
-        };
-
-        Ok(Vhd {
-            handle,
-            mode: open_mode,
-        })
+        let image = VhdImage::create_differencing(path, parent_path, options)?;
+        Ok(Self::from_backend(NativeBackend::from(image)))
+    }
+
+    /// Wraps an already opened [`VirtualDiskBackend`].
+    pub fn from_backend<B: VirtualDiskBackend + 'static>(backend: B) -> Self {
+        Vhd {
+            backend: Box::new(backend),
+        }
     }
 
     /// Mounts the given [`Vhd`] to a Windows device.
@@ -236,60 +351,52 @@ impl Vhd {
     /// A `char` representing the device letter where the [`Vhd`] was successfully mounted.
     ///
     /// # Errors
-    /// If Windows fails to mount the virtual disk.
+    /// If the backend fails to mount the virtual disk, or an [`ErrorKind::MountDriveDetection`]
+    /// error if no new drive letter appeared.
     pub fn attach(&mut self, persistent: bool) -> Result<char> {
-        let mut flags = 0;
+        let drives_before = self.backend.drive_letters();
 
-        if matches!(self.mode, OpenMode::ReadOnly) {
-            flags |= ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY;
-        }
+        self.backend.attach(persistent)?;
 
-        if persistent {
-            flags |= ATTACH_VIRTUAL_DISK_FLAG_PERMANENT_LIFETIME;
-        }
+        let drives_after = self.backend.drive_letters();
 
-        let drives_before = get_drive_letters();
-
-        let attach_result = unsafe {
-            AttachVirtualDisk(
-                self.handle,
-                std::ptr::null_mut(),
-                flags,
-                0,
-                std::ptr::null(),
-                std::ptr::null(),
-            )
-        };
-
-        match attach_result {
-            ERROR_SUCCESS => {
-                let drives_after = get_drive_letters();
-
-                let new_drive = get_new_drive_letter(&drives_before, &drives_after)
-                    .ok_or(Error::MountDriveDetection)?;
-
-                Ok(new_drive)
-            }
-            e => Err(e.into()),
-        }
+        get_new_drive_letter(&drives_before, &drives_after)
+            .ok_or_else(|| Error::new(ErrorKind::MountDriveDetection, "attach"))
     }
 
     /// Detaches a VHD specified by `path`.
     ///
-    /// This function will return an `ERROR_NOT_READY` if an attempt is made to detach a VHD that
-    /// has not been attached. Manual detachment is only necessary if the VHD was attached in
+    /// This function will return an [`ErrorKind::NotAttached`] error if an attempt is made to
+    /// detach a VHD that has not been attached. Manual detachment is only necessary if the VHD was attached in
     /// persistent mode. Otherwise, the [`Vhd`] will be automatically detached when it is dropped.
     ///
     /// # Errors
-    /// If Windows fails to detach the virtual disk.
+    /// If Windows fails to detach the virtual disk, or an [`ErrorKind::Unsupported`] error on
+    /// other platforms.
     pub fn detach<P: AsRef<OsStr>>(path: P) -> Result<()> {
-        let inner_vhd = Self::open(path, OpenMode::ReadOnly, None, VIRTUAL_DISK_ACCESS_DETACH)?;
-        let result =
-            unsafe { DetachVirtualDisk(inner_vhd.handle, DETACH_VIRTUAL_DISK_FLAG_NONE, 0) };
-        if result != ERROR_SUCCESS {
-            return Err(result.into());
+        #[cfg(windows)]
+        {
+            VirtDiskBackend::open_for_detach(path)?.detach()
         }
-        Ok(())
+
+        #[cfg(not(windows))]
+        {
+            NativeBackend::open(path.as_ref(), OpenMode::ReadOnly, None)?.detach()
+        }
+    }
+
+    /// Restores a missing or invalid trailing footer of the dynamic or differencing VHD at
+    /// `path` from the footer copy at offset 0, as left behind when the host crashes while the
+    /// image is being extended. The file must not be open.
+    ///
+    /// # Returns
+    /// What was changed, or `None` if the footer was valid and the file was left untouched.
+    ///
+    /// # Errors
+    /// An [`ErrorKind::Corrupt`] error if the copy cannot be used either, or an error if the file
+    /// cannot be read or written. See [`VhdImage::repair_footer`].
+    pub fn repair_footer<P: AsRef<Path>>(path: P) -> Result<Option<FooterRepair>> {
+        VhdImage::repair_footer(path)
     }
 
     /// Retrieves the size information of the [`Vhd`], including `VirtualSize` (u64),
@@ -299,31 +406,9 @@ impl Vhd {
     /// A [`DiskInfo`] struct with the size details.
     ///
     /// # Errors
-    /// If Windows fails to retrieve the information.
+    /// If the backend fails to retrieve the information.
     pub fn get_size(&mut self) -> Result<DiskInfo> {
-        let mut info = GET_VIRTUAL_DISK_INFO {
-            Version: GET_VIRTUAL_DISK_INFO_SIZE,
-            Anonymous: GET_VIRTUAL_DISK_INFO_0 {
-                Size: GET_VIRTUAL_DISK_INFO_0_3 {
-                    VirtualSize: 0,
-                    PhysicalSize: 0,
-                    BlockSize: 0,
-                    SectorSize: 0,
-                },
-            },
-        };
-
-        let mut info_size = std::mem::size_of::<GET_VIRTUAL_DISK_INFO>() as u32;
-
-        let result = unsafe {
-            GetVirtualDiskInformation(self.handle, &mut info_size, &mut info, std::ptr::null_mut())
-        };
-
-        if result != ERROR_SUCCESS {
-            return Err(result.into());
-        }
-
-        unsafe { Ok(info.Anonymous.Size.into()) }
+        self.backend.get_size()
     }
 
     /// Retrieves the unique identifier (`VhdIdentifier`) of the attached [`Vhd`].
@@ -334,59 +419,61 @@ impl Vhd {
     /// [`VhdIdentifier`] of the virtual disk
     ///
     /// # Errors
-    /// If Windows fails to retrieve the identifier.
+    /// If the backend fails to retrieve the identifier.
     pub fn get_identifier(&mut self) -> Result<VhdIdentifier> {
-        let mut info = GET_VIRTUAL_DISK_INFO {
-            Version: GET_VIRTUAL_DISK_INFO_IDENTIFIER,
-            Anonymous: GET_VIRTUAL_DISK_INFO_0 {
-                Identifier: GUID {
-                    data1: 0,
-                    data2: 0,
-                    data3: 0,
-                    data4: [0; 8],
-                },
-            },
-        };
-
-        let mut info_size = std::mem::size_of::<GET_VIRTUAL_DISK_INFO>() as u32;
-
-        let result = unsafe {
-            GetVirtualDiskInformation(self.handle, &mut info_size, &mut info, std::ptr::null_mut())
-        };
-
-        if result != ERROR_SUCCESS {
-            return Err(result.into());
-        }
+        self.backend.get_identifier()
+    }
 
-        let identifier: VhdIdentifier = unsafe { info.Anonymous.Identifier.into() };
-        Ok(identifier)
+    /// Retrieves the descriptive metadata of the [`Vhd`]: the application, version and host OS
+    /// that created it, its format version, when it was last written, whether it is in a
+    /// saved state and its disk type.
+    ///
+    /// # Errors
+    /// If the backend fails to retrieve the metadata, or an [`ErrorKind::Unsupported`] error if
+    /// it cannot read the image itself, like the `VirtDiskBackend`.
+    pub fn get_metadata(&mut self) -> Result<VhdMetadata> {
+        self.backend.get_metadata()
     }
 }
 
-fn get_drive_letters() -> Vec<char> {
-    // NOTE: 512 is more than enough
-    let mut buffer: [u16; 512] = [0; 512];
-    let buffer_len = unsafe { GetLogicalDriveStringsW(buffer.len() as u32, buffer.as_mut_ptr()) };
-
-    // Pre-allocate vector for drive letters (estimate 1 char per 4 wide chars)
-    let mut drive_letters = Vec::with_capacity((buffer_len / 4) as usize);
-
-    let mut start = 0;
-    for i in 0..buffer_len as usize {
-        if buffer[i] == 0 {
-            if start < i {
-                // Get the first character of the wide string slice and convert it to char
-                if let Some(first_char) = std::char::from_u32(u32::from(buffer[start])) {
-                    drive_letters.push(first_char);
-                }
-            }
-            start = i + 1;
-        }
+/// Reads the contents of the virtual disk, if the backend gives access to them.
+impl Read for Vhd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        contents(&mut self.backend)?.read(buf)
+    }
+}
+
+/// Writes the contents of the virtual disk, if the backend gives access to them.
+impl Write for Vhd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        contents(&mut self.backend)?.write(buf)
     }
 
-    drive_letters
+    fn flush(&mut self) -> io::Result<()> {
+        contents(&mut self.backend)?.flush()
+    }
+}
+
+/// Seeks within the contents of the virtual disk, if the backend gives access to them.
+impl Seek for Vhd {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        contents(&mut self.backend)?.seek(pos)
+    }
 }
 
+fn contents(backend: &mut Box<dyn VirtualDiskBackend>) -> io::Result<&mut dyn DiskContents> {
+    backend.contents().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the backend does not give access to the disk contents",
+        )
+    })
+}
+
+/// Returns the first letter in `after` that is not in `before`.
+///
+/// Both lists are in ascending order, so if several drives appeared (a disk with multiple
+/// volumes, or another device mounted at the same time) the lowest new letter is returned.
 fn get_new_drive_letter(before: &[char], after: &[char]) -> Option<char> {
     after
         .iter()
@@ -397,85 +484,32 @@ fn get_new_drive_letter(before: &[char], after: &[char]) -> Option<char> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::Path;
-    use std::thread::sleep;
-    use std::time::Duration;
-
-    // NOTE: Adding an initial sleep to ensure Windows has enough time to stabilize before running
-    // the tests, as they execute too quickly, even in single-threaded mode.
-
-    #[test]
-    fn get_size() {
-        sleep(Duration::from_secs(1));
-        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
-        let info = vhd.get_size();
-        dbg!(&info);
-    }
-
-    #[test]
-    fn get_identifier() {
-        sleep(Duration::from_secs(1));
-        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
-        let info = vhd.get_identifier().unwrap();
-        dbg!(&info);
-    }
-
-    #[test]
-    fn mount_vhd_read_only_temporary() {
-        sleep(Duration::from_secs(1));
-        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
-        let letter = vhd.attach(false).unwrap();
-        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
-        drop(vhd);
-        assert!(!Path::new(&format!(r"{letter}:\")).is_dir());
-    }
-
-    #[test]
-    fn mount_vhd_read_write_temporary() {
-        sleep(Duration::from_secs(1));
-        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadWrite, None).unwrap();
-        let letter = vhd.attach(false).unwrap();
-        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
-        drop(vhd);
-        assert!(!Path::new(&format!(r"{letter}:\")).is_dir());
-    }
-
-    #[test]
-    fn mount_vhd_read_only_permanent() {
-        sleep(Duration::from_secs(1));
-        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
-        let letter = vhd.attach(true).unwrap();
-        drop(vhd);
-        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
-        Vhd::detach("file.vhd").unwrap();
-    }
-
-    #[test]
-    fn mount_vhd_read_write_permanent() {
-        sleep(Duration::from_secs(1));
-        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadWrite, None).unwrap();
-        let letter = vhd.attach(true).unwrap();
-        drop(vhd);
-        assert!(Path::new(&format!(r"{letter}:\")).is_dir());
-        Vhd::detach("file.vhd").unwrap();
-    }
 
-    // for manual testing
-    #[ignore]
     #[test]
-    fn attach() {
-        let mut vhd = Vhd::new("file.vhd", OpenMode::ReadOnly, None).unwrap();
-        let attach_result = vhd.attach(true);
-        dbg!(&attach_result);
-        assert!(attach_result.is_ok());
+    fn vhd_type_from_extension() {
+        assert_eq!(
+            VhdType::from_extension(Path::new("file.vhd")).unwrap(),
+            VhdType::Vhd
+        );
+        assert_eq!(
+            VhdType::from_extension(Path::new("FILE.VHDX")).unwrap(),
+            VhdType::Vhdx
+        );
+
+        let error = VhdType::from_extension(Path::new("file.img")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(error.path(), Some(Path::new("file.img")));
+
+        let error = VhdType::from_extension(Path::new("file")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
     }
 
-    // for manual testing
-    #[ignore]
     #[test]
-    fn detach() {
-        let result = Vhd::detach("file.vhd");
-        dbg!(&result);
-        assert!(result.is_ok());
+    fn new_drive_letter() {
+        assert_eq!(
+            get_new_drive_letter(&['C', 'D'], &['C', 'D', 'E']),
+            Some('E')
+        );
+        assert_eq!(get_new_drive_letter(&['C', 'D'], &['C', 'D']), None);
     }
 }
@@ -0,0 +1,354 @@
+//! Helpers shared by the unit tests.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use uuid::Uuid;
+
+use crate::vhdx::metadata::{
+    FILE_PARAMETERS, LOGICAL_SECTOR_SIZE, PAGE_83_DATA, PARENT_LOCATOR, PHYSICAL_SECTOR_SIZE,
+    VIRTUAL_DISK_SIZE,
+};
+use crate::vhdx::region::{BAT_REGION, METADATA_REGION};
+use crate::{
+    DiskGeometry, DiskType, DynamicHeader, FileIdentifier, FileParameters, MetadataTable,
+    MetadataTableEntry, ParentLocator, PlatformCode, RegionTable, RegionTableEntry, VhdFooter,
+    VhdxHeader, VhdxMetadata,
+};
+
+/// A uniquely named file in the temporary directory that is removed when dropped.
+pub(crate) struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Reserves a unique path ending in `name` without creating the file.
+    pub(crate) fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("vhdrs-{}-{id}-{name}", std::process::id()));
+        TempFile { path }
+    }
+
+    /// Creates a unique file ending in `name` with the given contents.
+    pub(crate) fn with_contents(name: &str, contents: &[u8]) -> Self {
+        let file = TempFile::new(name);
+        std::fs::write(&file.path, contents).unwrap();
+        file
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Returns the footer of a fixed VHD of `size` bytes.
+pub(crate) fn fixed_footer(size: u64) -> VhdFooter {
+    VhdFooter {
+        features: 2,
+        format_version: 0x0001_0000,
+        data_offset: u64::MAX,
+        timestamp: 0,
+        creator_application: *b"test",
+        creator_version: 0,
+        creator_host_os: 0x5769_326b,
+        original_size: size,
+        current_size: size,
+        geometry: DiskGeometry {
+            cylinders: 0,
+            heads: 0,
+            sectors_per_track: 0,
+        },
+        disk_type: DiskType::Fixed,
+        checksum: 0,
+        unique_id: Uuid::from_u128(1),
+        saved_state: false,
+    }
+}
+
+/// Writes a fixed VHD with the given virtual disk contents.
+pub(crate) fn fixed_vhd(data: &[u8]) -> TempFile {
+    let mut bytes = data.to_vec();
+    bytes.extend_from_slice(&fixed_footer(data.len() as u64).to_bytes());
+    TempFile::with_contents("fixed.vhd", &bytes)
+}
+
+/// A block of a dynamic VHD built by [`dynamic_vhd_bytes`]: its index, its sector bitmap and its
+/// data.
+pub(crate) struct Block {
+    pub(crate) index: usize,
+    pub(crate) bitmap: Vec<u8>,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Block {
+    /// A block whose sectors are all present.
+    pub(crate) fn full(index: usize, data: Vec<u8>) -> Self {
+        Block {
+            index,
+            bitmap: vec![0xff; data.len() / 512 / 8],
+            data,
+        }
+    }
+}
+
+/// Returns the footer and dynamic disk header of a dynamic VHD of `size` bytes with a BAT at
+/// offset 1536.
+pub(crate) fn dynamic_structures(size: u64, block_size: u32) -> (VhdFooter, DynamicHeader) {
+    let footer = VhdFooter {
+        data_offset: 512,
+        disk_type: DiskType::Dynamic,
+        ..fixed_footer(size)
+    };
+    let header = DynamicHeader {
+        data_offset: u64::MAX,
+        table_offset: 1536,
+        header_version: 0x0001_0000,
+        max_table_entries: size.div_ceil(u64::from(block_size)) as u32,
+        block_size,
+        checksum: 0,
+        parent_unique_id: Uuid::nil(),
+        parent_timestamp: 0,
+        parent_name: String::new(),
+        parent_locators: Default::default(),
+    };
+    (footer, header)
+}
+
+/// Builds a dynamic VHD from its structures and allocated blocks, which are stored in order
+/// after the BAT.
+pub(crate) fn dynamic_vhd_bytes(
+    footer: &VhdFooter,
+    header: &DynamicHeader,
+    blocks: &[Block],
+) -> Vec<u8> {
+    let mut bytes = footer.to_bytes().to_vec();
+    bytes.extend_from_slice(&header.to_bytes());
+
+    let mut bat = vec![u32::MAX; header.max_table_entries as usize];
+    let mut block_bytes = Vec::new();
+    let blocks_start = header.table_offset + header.table_size();
+    for block in blocks {
+        bat[block.index] = ((blocks_start + block_bytes.len() as u64) / 512) as u32;
+        let mut bitmap = block.bitmap.clone();
+        bitmap.resize(header.bitmap_size() as usize, 0);
+        block_bytes.extend_from_slice(&bitmap);
+        block_bytes.extend_from_slice(&block.data);
+        block_bytes.resize(
+            block_bytes.len() + header.block_size as usize - block.data.len(),
+            0,
+        );
+    }
+
+    bytes.resize(header.table_offset as usize, 0);
+    bytes.extend(bat.iter().flat_map(|entry| entry.to_be_bytes()));
+    bytes.resize(blocks_start as usize, 0);
+    bytes.extend_from_slice(&block_bytes);
+    bytes.extend_from_slice(&footer.to_bytes());
+    bytes
+}
+
+/// Writes a dynamic VHD of `size` bytes with the given allocated blocks.
+pub(crate) fn dynamic_vhd(size: u64, block_size: u32, blocks: &[Block]) -> TempFile {
+    let (footer, header) = dynamic_structures(size, block_size);
+    TempFile::with_contents("dynamic.vhd", &dynamic_vhd_bytes(&footer, &header, blocks))
+}
+
+/// Writes a differencing VHD of `size` bytes with the given allocated blocks and unique ID 2.
+/// Its parent is `parent`, located through a W2ru locator holding `.\<file name>`.
+pub(crate) fn differencing_vhd(
+    size: u64,
+    block_size: u32,
+    parent: &Path,
+    parent_unique_id: Uuid,
+    blocks: &[Block],
+) -> TempFile {
+    let parent_name = parent.file_name().unwrap().to_str().unwrap();
+    let locator_data: Vec<u8> = format!(".\\{parent_name}")
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+
+    let (mut footer, mut header) = dynamic_structures(size, block_size);
+    footer.disk_type = DiskType::Differencing;
+    footer.unique_id = Uuid::from_u128(2);
+    header.table_offset = 2048;
+    header.parent_unique_id = parent_unique_id;
+    header.parent_name = parent_name.to_owned();
+    header.parent_locators[0] = ParentLocator {
+        platform_code: PlatformCode::W2ru,
+        data_space: 512,
+        data_length: locator_data.len() as u32,
+        data_offset: 1536,
+    };
+
+    let mut bytes = dynamic_vhd_bytes(&footer, &header, blocks);
+    bytes[1536..1536 + locator_data.len()].copy_from_slice(&locator_data);
+    TempFile::with_contents("differencing.vhd", &bytes)
+}
+
+/// Returns the metadata of a VHDX of `size` bytes with 512-byte sectors and virtual disk ID 3.
+pub(crate) fn vhdx_metadata(size: u64, block_size: u32) -> VhdxMetadata {
+    VhdxMetadata {
+        file_parameters: FileParameters {
+            block_size,
+            leave_blocks_allocated: false,
+            has_parent: false,
+        },
+        virtual_disk_size: size,
+        logical_sector_size: 512,
+        physical_sector_size: 512,
+        page_83_data: Uuid::from_u128(3),
+        parent_locator: None,
+    }
+}
+
+/// Serializes `metadata` into a metadata region of 1 MiB, with the items after the table.
+pub(crate) fn vhdx_metadata_region(metadata: &VhdxMetadata) -> Vec<u8> {
+    let parameters = &metadata.file_parameters;
+    let flags =
+        u32::from(parameters.leave_blocks_allocated) | u32::from(parameters.has_parent) << 1;
+    let mut items = vec![
+        (
+            FILE_PARAMETERS,
+            [parameters.block_size.to_le_bytes(), flags.to_le_bytes()].concat(),
+        ),
+        (
+            VIRTUAL_DISK_SIZE,
+            metadata.virtual_disk_size.to_le_bytes().to_vec(),
+        ),
+        (
+            LOGICAL_SECTOR_SIZE,
+            metadata.logical_sector_size.to_le_bytes().to_vec(),
+        ),
+        (
+            PHYSICAL_SECTOR_SIZE,
+            metadata.physical_sector_size.to_le_bytes().to_vec(),
+        ),
+        (PAGE_83_DATA, metadata.page_83_data.to_bytes_le().to_vec()),
+    ];
+    if let Some(locator) = &metadata.parent_locator {
+        let count = locator.entries.len();
+        let mut header = locator.locator_type.to_bytes_le().to_vec();
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&(count as u16).to_le_bytes());
+        let mut strings = Vec::new();
+        let mut string_offset = |string: &str| {
+            let offset = (20 + count * 12 + strings.len()) as u32;
+            strings.extend(string.encode_utf16().flat_map(u16::to_le_bytes));
+            (offset, (string.len() * 2) as u16)
+        };
+        for (key, value) in &locator.entries {
+            let (key_offset, key_length) = string_offset(key);
+            let (value_offset, value_length) = string_offset(value);
+            header.extend_from_slice(&key_offset.to_le_bytes());
+            header.extend_from_slice(&value_offset.to_le_bytes());
+            header.extend_from_slice(&key_length.to_le_bytes());
+            header.extend_from_slice(&value_length.to_le_bytes());
+        }
+        header.extend_from_slice(&strings);
+        items.push((PARENT_LOCATOR, header));
+    }
+
+    let mut region = vec![0; 1 << 20];
+    let mut table = MetadataTable {
+        entries: Vec::new(),
+    };
+    let mut offset = 64 << 10;
+    for (item_id, data) in items {
+        region[offset..offset + data.len()].copy_from_slice(&data);
+        table.entries.push(MetadataTableEntry {
+            item_id,
+            offset: offset as u32,
+            length: data.len() as u32,
+            is_user: false,
+            is_virtual_disk: item_id != FILE_PARAMETERS,
+            required: true,
+        });
+        offset += data.len().next_multiple_of(4096);
+    }
+    region[..64 << 10].copy_from_slice(&table.to_bytes()[..]);
+    region
+}
+
+/// Returns the current header of the VHDX files built by [`vhdx_bytes`], with the log at 1 MiB.
+pub(crate) fn vhdx_header(sequence_number: u64) -> VhdxHeader {
+    VhdxHeader {
+        checksum: 0,
+        sequence_number,
+        file_write_guid: Uuid::from_u128(4),
+        data_write_guid: Uuid::from_u128(5),
+        log_guid: Uuid::nil(),
+        log_version: 0,
+        version: 1,
+        log_length: 1 << 20,
+        log_offset: 1 << 20,
+    }
+}
+
+/// Builds a 4 MiB VHDX with `metadata`: an empty log at 1 MiB, the metadata region at 2 MiB and
+/// the BAT region at 3 MiB, both 1 MiB long.
+pub(crate) fn vhdx_bytes(metadata: &VhdxMetadata) -> Vec<u8> {
+    let mut bytes = vec![0; 4 << 20];
+    let identifier = FileIdentifier {
+        creator: "test".to_owned(),
+    };
+    let identifier = identifier.to_bytes();
+    bytes[..identifier.len()].copy_from_slice(&identifier);
+
+    for (index, offset) in [64 << 10, 128 << 10].into_iter().enumerate() {
+        bytes[offset..offset + 4096].copy_from_slice(&vhdx_header(index as u64).to_bytes());
+    }
+
+    let regions = RegionTable {
+        checksum: 0,
+        entries: vec![
+            RegionTableEntry {
+                guid: METADATA_REGION,
+                file_offset: 2 << 20,
+                length: 1 << 20,
+                required: true,
+            },
+            RegionTableEntry {
+                guid: BAT_REGION,
+                file_offset: 3 << 20,
+                length: 1 << 20,
+                required: true,
+            },
+        ],
+    };
+    let regions = regions.to_bytes();
+    for offset in [192 << 10, 256 << 10] {
+        bytes[offset..offset + (64 << 10)].copy_from_slice(&regions[..]);
+    }
+
+    bytes[2 << 20..3 << 20].copy_from_slice(&vhdx_metadata_region(metadata));
+    bytes
+}
+
+/// Writes a VHDX of `size` bytes without allocated blocks.
+pub(crate) fn vhdx(size: u64, block_size: u32) -> TempFile {
+    TempFile::with_contents("image.vhdx", &vhdx_bytes(&vhdx_metadata(size, block_size)))
+}
+
+/// Builds a VHDX with `metadata` like [`vhdx_bytes`], with the given fully present blocks stored
+/// in order from 4 MiB, each followed by zeros up to the block size.
+pub(crate) fn vhdx_with_blocks(metadata: &VhdxMetadata, blocks: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = vhdx_bytes(metadata);
+    let block_size = u64::from(metadata.file_parameters.block_size);
+    let chunk_ratio = (1 << 23) * u64::from(metadata.logical_sector_size) / block_size;
+    for (block, data) in blocks {
+        let file_offset = bytes.len() as u64;
+        let entry = (3 << 20) + ((block + block / chunk_ratio) * 8) as usize;
+        bytes[entry..entry + 8].copy_from_slice(&(6 | file_offset).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes.resize((file_offset + block_size) as usize, 0);
+    }
+    bytes
+}
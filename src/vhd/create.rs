@@ -0,0 +1,579 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Component, Path};
+use std::time::SystemTime;
+
+use uuid::Uuid;
+
+use super::dynamic::{HEADER_SIZE, HEADER_VERSION, UNUSED_ENTRY};
+use super::footer::{FOOTER_SIZE, FORMAT_VERSION};
+use super::image::write_all_at;
+use super::metadata::from_system_time;
+use super::{
+    DiskGeometry, DiskType, DynamicHeader, ParentLocator, PlatformCode, VhdFooter, VhdImage,
+};
+use crate::{Error, ErrorKind, OpenMode, Result};
+
+/// Four-character code of this crate, written as the creator application of new images.
+const CREATOR_APPLICATION: [u8; 4] = *b"vhrs";
+/// `Wi2k`, the host OS written to new images whatever the platform, as Windows expects.
+const CREATOR_HOST_OS: u32 = 0x5769_326b;
+/// Largest virtual disk size Windows accepts for a VHD, 2040 GiB.
+pub(crate) const MAX_SIZE: u64 = 2040 << 30;
+/// Size of the zeroed chunks written to preallocate an image.
+const ZERO_CHUNK_SIZE: usize = 1 << 20;
+/// Smallest and largest block size of new dynamic VHDs. 2 MiB is also the default.
+const MIN_BLOCK_SIZE: u32 = 512 << 10;
+const MAX_BLOCK_SIZE: u32 = 2 << 20;
+
+/// How the host file of a new fixed VHD is allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Allocation {
+    /// The file is extended without writing the virtual disk, so it only takes space for the
+    /// data written later on file systems that support sparse files.
+    #[default]
+    Sparse,
+    /// The whole virtual disk is written with zeros, so the space is reserved up front.
+    Preallocate,
+}
+
+/// Options for creating a VHD, see [`Vhd::create_fixed_with`](crate::Vhd::create_fixed_with)
+/// and [`Vhd::create_dynamic_with`](crate::Vhd::create_dynamic_with).
+#[derive(Debug, Clone)]
+pub struct CreateOptions {
+    allocation: Allocation,
+    block_size: u32,
+    bat_alignment: u64,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        CreateOptions {
+            allocation: Allocation::Sparse,
+            block_size: MAX_BLOCK_SIZE,
+            bat_alignment: 512,
+        }
+    }
+}
+
+impl CreateOptions {
+    /// Returns the default options: a [`Allocation::Sparse`] host file, 2 MiB blocks and a BAT
+    /// aligned to a sector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how the host file of a fixed VHD is allocated. Ignored for dynamic VHDs.
+    pub fn allocation(mut self, allocation: Allocation) -> Self {
+        self.allocation = allocation;
+        self
+    }
+
+    /// Sets the block size of a dynamic VHD in bytes: a power of two from 512 KiB to 2 MiB.
+    pub fn block_size(mut self, block_size: u32) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Sets the alignment in bytes of the BAT of a dynamic VHD: a power of two of at least 512.
+    /// The space after the BAT is padded to the same alignment, so the first block starts
+    /// aligned too.
+    pub fn bat_alignment(mut self, bat_alignment: u64) -> Self {
+        self.bat_alignment = bat_alignment;
+        self
+    }
+
+    /// Checks the options that apply to dynamic VHDs.
+    fn check_dynamic(&self) -> Result<()> {
+        if !self.block_size.is_power_of_two()
+            || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&self.block_size)
+        {
+            return Err(
+                Error::new(ErrorKind::InvalidInput, "create").with_source(format!(
+                    "block size {} is not a power of two from 512 KiB to 2 MiB",
+                    self.block_size
+                )),
+            );
+        }
+        if !self.bat_alignment.is_power_of_two() || self.bat_alignment < 512 {
+            return Err(
+                Error::new(ErrorKind::InvalidInput, "create").with_source(format!(
+                    "BAT alignment {} is not a power of two of at least 512",
+                    self.bat_alignment
+                )),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl VhdImage {
+    /// Creates a fixed VHD of `size` bytes at `path` and opens it in [`OpenMode::ReadWrite`].
+    ///
+    /// The footer gets the geometry of the specification for `size`, this crate as the creator
+    /// application, the current time and a random unique ID. The virtual disk reads as zeros.
+    ///
+    /// # Errors
+    /// An [`ErrorKind::InvalidInput`] error if `size` is zero, not a multiple of 512 or larger
+    /// than 2040 GiB, an [`ErrorKind::AlreadyExists`] error if the file exists, or an error if
+    /// the file cannot be written. A partially written file is removed.
+    pub fn create_fixed<P: AsRef<Path>>(
+        path: P,
+        size: u64,
+        options: &CreateOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        check_size(size).map_err(|e| e.with_path(path))?;
+
+        let footer = new_footer(DiskType::Fixed, size, u64::MAX);
+        create_file(path, |file| {
+            if options.allocation == Allocation::Preallocate {
+                let zeros = vec![0; ZERO_CHUNK_SIZE];
+                let mut offset = 0;
+                while offset < size {
+                    let len = (size - offset).min(ZERO_CHUNK_SIZE as u64) as usize;
+                    write_all_at(file, offset, &zeros[..len])?;
+                    offset += len as u64;
+                }
+            }
+            file.set_len(size + FOOTER_SIZE as u64)?;
+            write_all_at(file, size, &footer.to_bytes())
+        })?;
+
+        VhdImage::open(path, OpenMode::ReadWrite)
+    }
+
+    /// Creates a dynamic VHD of `size` bytes at `path` and opens it in [`OpenMode::ReadWrite`].
+    ///
+    /// The file holds a copy of the footer at offset 0, the dynamic disk header, a BAT with one
+    /// unused entry per block of the virtual disk and the footer. No block is allocated, so the
+    /// virtual disk reads as zeros.
+    ///
+    /// # Errors
+    /// An [`ErrorKind::InvalidInput`] error if `size` is zero, not a multiple of 512 or larger
+    /// than 2040 GiB or if the block size or BAT alignment of `options` is invalid, an
+    /// [`ErrorKind::AlreadyExists`] error if the file exists, or an error if the file cannot be
+    /// written. A partially written file is removed.
+    pub fn create_dynamic<P: AsRef<Path>>(
+        path: P,
+        size: u64,
+        options: &CreateOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        check_size(size)
+            .and_then(|()| options.check_dynamic())
+            .map_err(|e| e.with_path(path))?;
+
+        let footer = new_footer(DiskType::Dynamic, size, FOOTER_SIZE as u64);
+        let header = new_header(size, options, (FOOTER_SIZE + HEADER_SIZE) as u64);
+        let bytes = dynamic_bytes(&footer, &header, options.bat_alignment);
+        create_file(path, |file| write_all_at(file, 0, &bytes))?;
+
+        VhdImage::open(path, OpenMode::ReadWrite)
+    }
+
+    /// Creates a differencing VHD at `path` whose parent is the VHD at `parent_path`, and opens
+    /// it in [`OpenMode::ReadWrite`].
+    ///
+    /// The child has the size and geometry of the parent and records its unique ID,
+    /// modification time and file name. Its W2ru locator holds the path of the parent relative
+    /// to the child (omitted if there is none, e.g. on another drive) and its W2ku locator the
+    /// absolute path. The block size and BAT alignment are taken from `options`.
+    ///
+    /// # Errors
+    /// If the parent cannot be opened, an [`ErrorKind::InvalidInput`] error if the options or
+    /// the size of the parent are invalid, an [`ErrorKind::AlreadyExists`] error if the file
+    /// exists, or an error if the file cannot be written. A partially written file is removed.
+    pub fn create_differencing<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        parent_path: Q,
+        options: &CreateOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        options.check_dynamic().map_err(|e| e.with_path(path))?;
+
+        let parent = VhdImage::open(parent_path, OpenMode::ReadOnly)?;
+        let parent_path = parent
+            .path()
+            .canonicalize()
+            .map_err(|e| Error::io("create", e).with_path(parent.path()))?;
+        let modified = std::fs::metadata(&parent_path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| Error::io("create", e).with_path(&parent_path))?;
+
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        let directory = directory
+            .canonicalize()
+            .map_err(|e| Error::io("create", e).with_path(path))?;
+
+        let mut locator_paths = Vec::new();
+        if let Some(relative) = relative_path(&directory, &parent_path) {
+            locator_paths.push((PlatformCode::W2ru, relative));
+        }
+        locator_paths.push((PlatformCode::W2ku, windows_path(&parent_path)));
+
+        let size = parent.virtual_size();
+        check_size(size).map_err(|e| e.with_path(path))?;
+        let mut footer = new_footer(DiskType::Differencing, size, FOOTER_SIZE as u64);
+        footer.geometry = parent.footer().geometry;
+
+        // the locator data follows the header, each padded to a sector
+        let mut locators = Vec::new();
+        let mut data_offset = (FOOTER_SIZE + HEADER_SIZE) as u64;
+        for (platform_code, locator_path) in locator_paths {
+            let data: Vec<u8> = locator_path
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect();
+            let data_space = (data.len() as u64).next_multiple_of(512);
+            let locator = ParentLocator {
+                platform_code,
+                data_space: data_space as u32,
+                data_length: data.len() as u32,
+                data_offset,
+            };
+            locators.push((locator, data));
+            data_offset += data_space;
+        }
+
+        let mut header = new_header(size, options, data_offset);
+        header.parent_unique_id = parent.footer().unique_id;
+        header.parent_timestamp = from_system_time(modified);
+        header.parent_name = parent_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for (entry, (locator, _)) in header.parent_locators.iter_mut().zip(&locators) {
+            *entry = *locator;
+        }
+
+        let mut bytes = dynamic_bytes(&footer, &header, options.bat_alignment);
+        for (locator, data) in &locators {
+            let start = locator.data_offset as usize;
+            bytes[start..start + data.len()].copy_from_slice(data);
+        }
+        create_file(path, |file| write_all_at(file, 0, &bytes))?;
+
+        VhdImage::open(path, OpenMode::ReadWrite)
+    }
+}
+
+/// Returns the dynamic disk header of a new dynamic or differencing VHD of `size` bytes, whose
+/// BAT starts at the alignment of `options` after `data_end`, the end of the header and parent
+/// locator data.
+pub(crate) fn new_header(size: u64, options: &CreateOptions, data_end: u64) -> DynamicHeader {
+    DynamicHeader {
+        data_offset: u64::MAX,
+        table_offset: data_end.next_multiple_of(options.bat_alignment),
+        header_version: HEADER_VERSION,
+        max_table_entries: size.div_ceil(u64::from(options.block_size)) as u32,
+        block_size: options.block_size,
+        checksum: 0,
+        parent_unique_id: Uuid::nil(),
+        parent_timestamp: 0,
+        parent_name: String::new(),
+        parent_locators: Default::default(),
+    }
+}
+
+/// Lays out a new dynamic or differencing VHD without allocated blocks: the footer copy, the
+/// header, the BAT padded to `bat_alignment` and the footer. Space between the header and the
+/// BAT (such as parent locator data) is left zeroed for the caller to fill.
+pub(crate) fn dynamic_bytes(
+    footer: &VhdFooter,
+    header: &DynamicHeader,
+    bat_alignment: u64,
+) -> Vec<u8> {
+    let table_end = (header.table_offset + header.table_size()).next_multiple_of(bat_alignment);
+
+    let mut bytes = vec![0; table_end as usize];
+    bytes[..FOOTER_SIZE].copy_from_slice(&footer.to_bytes());
+    bytes[FOOTER_SIZE..FOOTER_SIZE + HEADER_SIZE].copy_from_slice(&header.to_bytes());
+
+    let table_offset = header.table_offset as usize;
+    let table_len = header.max_table_entries as usize * 4;
+    for entry in bytes[table_offset..table_offset + table_len].chunks_exact_mut(4) {
+        entry.copy_from_slice(&UNUSED_ENTRY.to_be_bytes());
+    }
+
+    bytes.extend_from_slice(&footer.to_bytes());
+    bytes
+}
+
+/// Checks that `size` is a valid virtual disk size.
+pub(crate) fn check_size(size: u64) -> Result<()> {
+    if size == 0 || !size.is_multiple_of(512) || size > MAX_SIZE {
+        return Err(
+            Error::new(ErrorKind::InvalidInput, "create").with_source(format!(
+                "virtual disk size {size} is not a non-zero multiple of 512 of at most 2040 GiB"
+            )),
+        );
+    }
+    Ok(())
+}
+
+/// Returns the footer of a new image of `disk_type` and `size` bytes whose dynamic disk header
+/// is at `data_offset`.
+pub(crate) fn new_footer(disk_type: DiskType, size: u64, data_offset: u64) -> VhdFooter {
+    let version = |component: &str| component.parse::<u32>().unwrap_or(0);
+
+    VhdFooter {
+        features: 2,
+        format_version: FORMAT_VERSION,
+        data_offset,
+        timestamp: from_system_time(SystemTime::now()),
+        creator_application: CREATOR_APPLICATION,
+        creator_version: version(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+            | version(env!("CARGO_PKG_VERSION_MINOR")),
+        creator_host_os: CREATOR_HOST_OS,
+        original_size: size,
+        current_size: size,
+        geometry: DiskGeometry::from_disk_size(size),
+        disk_type,
+        checksum: 0,
+        unique_id: Uuid::new_v4(),
+        saved_state: false,
+    }
+}
+
+/// Returns the path of `target` relative to `directory` in the Windows syntax of W2ru
+/// locators, e.g. `.\parent.vhd` or `..\base\parent.vhd`. Both paths must be canonical.
+/// Returns `None` if they do not share a root.
+fn relative_path(directory: &Path, target: &Path) -> Option<String> {
+    let directory: Vec<Component> = directory.components().collect();
+    let target: Vec<Component> = target.components().collect();
+
+    let common = directory
+        .iter()
+        .zip(&target)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return None;
+    }
+
+    let mut parts: Vec<String> = match directory.len() - common {
+        0 => vec![".".to_owned()],
+        up => vec!["..".to_owned(); up],
+    };
+    parts.extend(
+        target[common..]
+            .iter()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+    );
+    Some(parts.join("\\"))
+}
+
+/// Returns `path` in the Windows syntax of W2ku locators, without the `\\?\` prefix that
+/// canonical Windows paths carry.
+fn windows_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        match path.strip_prefix(r"\\?\UNC\") {
+            Some(unc) => format!(r"\\{unc}"),
+            None => path.strip_prefix(r"\\?\").unwrap_or(&path).to_owned(),
+        }
+    } else {
+        path.replace('/', "\\")
+    }
+}
+
+/// Creates the file at `path`, which must not exist, and fills it with `write`. The file is
+/// synced to disk, or removed if writing fails.
+pub(crate) fn create_file<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&File) -> io::Result<()>,
+{
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| Error::io("create", e).with_path(path))?;
+
+    if let Err(error) = write(&file).and_then(|()| file.sync_all()) {
+        drop(file);
+        let _ = std::fs::remove_file(path);
+        return Err(Error::io("create", error).with_path(path));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::test_util::{fixed_vhd, TempFile};
+
+    #[test]
+    fn create_fixed() {
+        let file = TempFile::new("created.vhd");
+        let mut image =
+            VhdImage::create_fixed(file.path(), 64 << 20, &CreateOptions::new()).unwrap();
+
+        let footer = image.footer().clone();
+        assert_eq!(footer.disk_type, DiskType::Fixed);
+        assert_eq!(footer.current_size, 64 << 20);
+        assert_eq!(footer.creator_application, *b"vhrs");
+        assert_eq!(footer.geometry, DiskGeometry::from_disk_size(64 << 20));
+        assert!(!footer.unique_id.is_nil());
+        assert_eq!(image.physical_size().unwrap(), (64 << 20) + 512);
+
+        image.seek(SeekFrom::Start(4096)).unwrap();
+        image.write_all(b"data").unwrap();
+        image.seek(SeekFrom::Start(4094)).unwrap();
+        let mut buf = [0xff; 8];
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"\0\0data\0\0");
+    }
+
+    #[test]
+    fn create_preallocated() {
+        let file = TempFile::new("preallocated.vhd");
+        let options = CreateOptions::new().allocation(Allocation::Preallocate);
+        let image = VhdImage::create_fixed(file.path(), (3 << 20) + 512, &options).unwrap();
+        assert_eq!(image.physical_size().unwrap(), (3 << 20) + 1024);
+
+        let reopened = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert_eq!(reopened.footer(), image.footer());
+    }
+
+    #[test]
+    fn create_dynamic() {
+        let file = TempFile::new("dynamic.vhd");
+        let options = CreateOptions::new().block_size(512 << 10);
+        let mut image = VhdImage::create_dynamic(file.path(), (5 << 20) + 512, &options).unwrap();
+
+        assert_eq!(image.footer().disk_type, DiskType::Dynamic);
+        assert_eq!(image.footer().data_offset, 512);
+        assert_eq!(image.block_size(), 512 << 10);
+        assert_eq!(image.bat().unwrap(), [UNUSED_ENTRY; 11]);
+        let header = image.dynamic_header().unwrap();
+        assert_eq!(header.table_offset, 1536);
+        assert_eq!(image.physical_size().unwrap(), 1536 + 512 + 512);
+
+        // the copy at offset 0 is identical to the footer
+        let bytes = std::fs::read(file.path()).unwrap();
+        assert_eq!(bytes[..512], bytes[bytes.len() - 512..]);
+
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, vec![0; (5 << 20) + 512]);
+    }
+
+    #[test]
+    fn create_dynamic_aligned() {
+        let file = TempFile::new("aligned.vhd");
+        let options = CreateOptions::new().bat_alignment(1 << 20);
+        let image = VhdImage::create_dynamic(file.path(), 1 << 30, &options).unwrap();
+
+        assert_eq!(image.block_size(), 2 << 20);
+        assert_eq!(image.bat().unwrap().len(), 512);
+        assert_eq!(image.dynamic_header().unwrap().table_offset, 1 << 20);
+        assert_eq!(image.physical_size().unwrap(), (2 << 20) + 512);
+    }
+
+    #[test]
+    fn invalid_dynamic_options() {
+        let file = TempFile::new("invalid-dynamic.vhd");
+        for options in [
+            CreateOptions::new().block_size(256 << 10),
+            CreateOptions::new().block_size(3 << 20),
+            CreateOptions::new().bat_alignment(768),
+        ] {
+            let error = VhdImage::create_dynamic(file.path(), 1 << 20, &options).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+        assert!(!file.path().exists());
+    }
+
+    #[test]
+    fn create_differencing() {
+        let data: Vec<u8> = (0..8192u32).map(|i| (i / 3) as u8).collect();
+        let parent = fixed_vhd(&data);
+        let child_file = TempFile::new("child.vhd");
+
+        let mut child =
+            VhdImage::create_differencing(child_file.path(), parent.path(), &CreateOptions::new())
+                .unwrap();
+        assert_eq!(child.footer().disk_type, DiskType::Differencing);
+        assert_eq!(child.virtual_size(), 8192);
+        assert_eq!(
+            child.parent().unwrap().footer().unique_id,
+            Uuid::from_u128(1)
+        );
+
+        let parent_name = parent.path().file_name().unwrap().to_str().unwrap();
+        let header = child.dynamic_header().unwrap().clone();
+        assert_eq!(header.parent_unique_id, Uuid::from_u128(1));
+        assert_eq!(header.parent_name, parent_name);
+        assert_ne!(header.parent_timestamp, 0);
+
+        let bytes = std::fs::read(child_file.path()).unwrap();
+        let locator_path = |locator: &ParentLocator| {
+            let start = locator.data_offset as usize;
+            locator
+                .decode_path(&bytes[start..start + locator.data_length as usize])
+                .unwrap()
+        };
+        let [relative, absolute, ..] = &header.parent_locators;
+        assert_eq!(relative.platform_code, PlatformCode::W2ru);
+        assert_eq!(locator_path(relative), format!(".\\{parent_name}"));
+        assert_eq!(absolute.platform_code, PlatformCode::W2ku);
+        assert!(locator_path(absolute).ends_with(&format!("\\{parent_name}")));
+
+        let mut contents = Vec::new();
+        child.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, data);
+    }
+
+    #[test]
+    fn relative_paths() {
+        let root = std::env::temp_dir().canonicalize().unwrap();
+        let relative = |directory: &[&str], target: &[&str]| {
+            let directory: PathBuf = directory.iter().fold(root.clone(), |p, c| p.join(c));
+            let target: PathBuf = target.iter().fold(root.clone(), |p, c| p.join(c));
+            relative_path(&directory, &target).unwrap()
+        };
+        assert_eq!(relative(&["a"], &["a", "p.vhd"]), ".\\p.vhd");
+        assert_eq!(relative(&["a", "b"], &["a", "p.vhd"]), "..\\p.vhd");
+        assert_eq!(
+            relative(&["a", "b"], &["c", "d", "p.vhd"]),
+            "..\\..\\c\\d\\p.vhd"
+        );
+        assert_eq!(relative(&["a"], &["a", "b", "p.vhd"]), ".\\b\\p.vhd");
+    }
+
+    #[test]
+    fn invalid_size() {
+        let file = TempFile::new("invalid.vhd");
+        for size in [0, 1000, MAX_SIZE + 512] {
+            let error =
+                VhdImage::create_fixed(file.path(), size, &CreateOptions::new()).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            assert_eq!(error.path(), Some(file.path()));
+        }
+        assert!(!file.path().exists());
+
+        let parent = fixed_vhd(&[0; 1000]);
+        let error =
+            VhdImage::create_differencing(file.path(), parent.path(), &CreateOptions::new())
+                .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(error.path(), Some(file.path()));
+        assert!(!file.path().exists());
+    }
+
+    #[test]
+    fn existing_file() {
+        let file = TempFile::with_contents("existing.vhd", b"keep");
+        let error = VhdImage::create_fixed(file.path(), 4096, &CreateOptions::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"keep");
+    }
+}
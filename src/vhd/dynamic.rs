@@ -0,0 +1,527 @@
+use std::fmt::{self, Display};
+
+use uuid::Uuid;
+
+use super::{be_u32, be_u64, checksum};
+use crate::{ParseError, Structure};
+
+/// Cookie that starts the dynamic disk header.
+pub(crate) const COOKIE: &[u8; 8] = b"cxsparse";
+/// Size of the dynamic disk header in bytes.
+pub(crate) const HEADER_SIZE: usize = 1024;
+/// Version 1.0 of the dynamic disk header.
+pub(crate) const HEADER_VERSION: u32 = 0x0001_0000;
+/// BAT entry of a block that is not allocated.
+pub(crate) const UNUSED_ENTRY: u32 = u32::MAX;
+/// Size of a sector in bytes.
+pub(crate) const SECTOR_SIZE: u64 = 512;
+
+const DATA_OFFSET: usize = 8;
+const TABLE_OFFSET: usize = 16;
+const HEADER_VERSION_OFFSET: usize = 24;
+const MAX_TABLE_ENTRIES: usize = 28;
+const BLOCK_SIZE: usize = 32;
+const CHECKSUM: usize = 36;
+const PARENT_UNIQUE_ID: usize = 40;
+const PARENT_TIMESTAMP: usize = 56;
+const PARENT_NAME: usize = 64;
+const PARENT_NAME_SIZE: usize = 512;
+const PARENT_LOCATORS: usize = 576;
+const LOCATOR_SIZE: usize = 24;
+/// Number of parent locator entries in the header.
+pub(crate) const LOCATOR_COUNT: usize = 8;
+
+/// The platform code of a parent locator entry, which tells how its data encodes the path of
+/// the parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PlatformCode {
+    /// The entry is not used.
+    #[default]
+    None,
+    /// Relative Windows path, stored as a NUL-terminated ANSI string. Deprecated.
+    Wi2r,
+    /// Absolute Windows path, stored as a NUL-terminated ANSI string. Deprecated.
+    Wi2k,
+    /// Relative Windows path, stored as UTF-16LE.
+    W2ru,
+    /// Absolute Windows path, stored as UTF-16LE.
+    W2ku,
+    /// Mac OS alias, stored as a blob.
+    Mac,
+    /// Mac OS X file URL, stored as UTF-8.
+    MacX,
+}
+
+impl PlatformCode {
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(PlatformCode::None),
+            0x5769_3272 => Some(PlatformCode::Wi2r),
+            0x5769_326b => Some(PlatformCode::Wi2k),
+            0x5732_7275 => Some(PlatformCode::W2ru),
+            0x5732_6b75 => Some(PlatformCode::W2ku),
+            0x4d61_6320 => Some(PlatformCode::Mac),
+            0x4d61_6358 => Some(PlatformCode::MacX),
+            _ => None,
+        }
+    }
+
+    fn to_u32(self) -> u32 {
+        match self {
+            PlatformCode::None => 0,
+            PlatformCode::Wi2r => 0x5769_3272,
+            PlatformCode::Wi2k => 0x5769_326b,
+            PlatformCode::W2ru => 0x5732_7275,
+            PlatformCode::W2ku => 0x5732_6b75,
+            PlatformCode::Mac => 0x4d61_6320,
+            PlatformCode::MacX => 0x4d61_6358,
+        }
+    }
+}
+
+impl Display for PlatformCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PlatformCode::None => "none",
+            PlatformCode::Wi2r => "Wi2r",
+            PlatformCode::Wi2k => "Wi2k",
+            PlatformCode::W2ru => "W2ru",
+            PlatformCode::W2ku => "W2ku",
+            PlatformCode::Mac => "Mac",
+            PlatformCode::MacX => "MacX",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A parent locator entry of the dynamic disk header of a differencing disk. It points to data
+/// elsewhere in the file that holds a path of the parent disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParentLocator {
+    /// How the locator data encodes the path.
+    pub platform_code: PlatformCode,
+    /// Space reserved for the locator data. Virtual PC stores it in sectors, Windows in bytes.
+    pub data_space: u32,
+    /// Length of the locator data in bytes.
+    pub data_length: u32,
+    /// Absolute offset of the locator data.
+    pub data_offset: u64,
+}
+
+impl ParentLocator {
+    fn from_bytes(bytes: &[u8], offset: u64) -> Result<Self, ParseError> {
+        let code = be_u32(bytes, 0);
+        let platform_code = PlatformCode::from_u32(code).ok_or_else(|| {
+            ParseError::new(Structure::ParentLocator, "platform code", offset)
+                .with_found(format!("{code:#010x}"))
+        })?;
+
+        Ok(ParentLocator {
+            platform_code,
+            data_space: be_u32(bytes, 4),
+            data_length: be_u32(bytes, 8),
+            data_offset: be_u64(bytes, 16),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; LOCATOR_SIZE] {
+        let mut bytes = [0; LOCATOR_SIZE];
+        bytes[..4].copy_from_slice(&self.platform_code.to_u32().to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.data_space.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.data_length.to_be_bytes());
+        bytes[16..].copy_from_slice(&self.data_offset.to_be_bytes());
+        bytes
+    }
+
+    /// Decodes the path stored in the locator `data`, as read from
+    /// [`ParentLocator::data_offset`]. Returns `None` for unused entries and Mac OS aliases,
+    /// which cannot be decoded.
+    pub fn decode_path(&self, data: &[u8]) -> Option<String> {
+        let path = match self.platform_code {
+            PlatformCode::None | PlatformCode::Mac => return None,
+            PlatformCode::W2ru | PlatformCode::W2ku => {
+                let units: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            PlatformCode::Wi2r | PlatformCode::Wi2k => String::from_utf8_lossy(data).into_owned(),
+            PlatformCode::MacX => {
+                let url = String::from_utf8_lossy(data);
+                let path = url.strip_prefix("file://").unwrap_or(&url);
+                path.strip_prefix("localhost").unwrap_or(path).to_owned()
+            }
+        };
+
+        let path = path.trim_end_matches('\0');
+        (!path.is_empty()).then(|| path.to_owned())
+    }
+}
+
+/// The dynamic disk header of dynamic and differencing VHDs, located at the data offset of the
+/// footer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicHeader {
+    /// Offset of the next structure; unused and set to [`u64::MAX`].
+    pub data_offset: u64,
+    /// Absolute offset of the Block Allocation Table.
+    pub table_offset: u64,
+    /// Header version, major version in the high 16 bits.
+    pub header_version: u32,
+    /// Number of entries in the Block Allocation Table.
+    pub max_table_entries: u32,
+    /// Size of a block in bytes, not including its sector bitmap.
+    pub block_size: u32,
+    /// The checksum stored in the header. [`DynamicHeader::to_bytes`] recomputes it.
+    pub checksum: u32,
+    /// Unique ID of the parent disk of a differencing disk, in its footer.
+    pub parent_unique_id: Uuid,
+    /// Modification time of the parent disk of a differencing disk, in seconds since
+    /// January 1, 2000 12:00:00 AM UTC.
+    pub parent_timestamp: u32,
+    /// File name of the parent disk of a differencing disk, stored as UTF-16BE.
+    pub parent_name: String,
+    /// Parent locator entries of a differencing disk.
+    pub parent_locators: [ParentLocator; LOCATOR_COUNT],
+}
+
+impl DynamicHeader {
+    /// Parses the header in `bytes`, which was read from file offset `offset`.
+    ///
+    /// # Errors
+    /// If the cookie, checksum, header version, block size or a parent locator platform code is
+    /// invalid.
+    pub fn parse(bytes: &[u8; HEADER_SIZE], offset: u64) -> Result<Self, ParseError> {
+        let field_offset = |field: usize| offset + field as u64;
+
+        if &bytes[..8] != COOKIE {
+            return Err(ParseError::mismatch(
+                Structure::DynamicHeader,
+                "cookie",
+                offset,
+                "\"cxsparse\"",
+                format!("{:?}", String::from_utf8_lossy(&bytes[..8])),
+            ));
+        }
+
+        let stored_checksum = be_u32(bytes, CHECKSUM);
+        let computed_checksum = checksum(bytes, CHECKSUM);
+        if stored_checksum != computed_checksum {
+            return Err(ParseError::mismatch(
+                Structure::DynamicHeader,
+                "checksum",
+                field_offset(CHECKSUM),
+                format!("{computed_checksum:#010x}"),
+                format!("{stored_checksum:#010x}"),
+            ));
+        }
+
+        let header_version = be_u32(bytes, HEADER_VERSION_OFFSET);
+        if header_version >> 16 != HEADER_VERSION >> 16 {
+            return Err(ParseError::mismatch(
+                Structure::DynamicHeader,
+                "header version",
+                field_offset(HEADER_VERSION_OFFSET),
+                format!("{HEADER_VERSION:#010x}"),
+                format!("{header_version:#010x}"),
+            ));
+        }
+
+        let block_size = be_u32(bytes, BLOCK_SIZE);
+        if !block_size.is_power_of_two() || u64::from(block_size) < SECTOR_SIZE {
+            return Err(ParseError::new(
+                Structure::DynamicHeader,
+                "block size",
+                field_offset(BLOCK_SIZE),
+            )
+            .with_expected("a power of two of at least 512")
+            .with_found(block_size));
+        }
+
+        let name_units: Vec<u16> = bytes[PARENT_NAME..PARENT_NAME + PARENT_NAME_SIZE]
+            .chunks_exact(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+
+        let mut parent_locators = [ParentLocator::default(); LOCATOR_COUNT];
+        for (index, locator) in parent_locators.iter_mut().enumerate() {
+            let start = PARENT_LOCATORS + index * LOCATOR_SIZE;
+            *locator = ParentLocator::from_bytes(
+                &bytes[start..start + LOCATOR_SIZE],
+                field_offset(start),
+            )?;
+        }
+
+        Ok(DynamicHeader {
+            data_offset: be_u64(bytes, DATA_OFFSET),
+            table_offset: be_u64(bytes, TABLE_OFFSET),
+            header_version,
+            max_table_entries: be_u32(bytes, MAX_TABLE_ENTRIES),
+            block_size,
+            checksum: stored_checksum,
+            parent_unique_id: Uuid::from_bytes_le(
+                bytes[PARENT_UNIQUE_ID..PARENT_UNIQUE_ID + 16]
+                    .try_into()
+                    .unwrap(),
+            ),
+            parent_timestamp: be_u32(bytes, PARENT_TIMESTAMP),
+            parent_name: String::from_utf16_lossy(&name_units),
+            parent_locators,
+        })
+    }
+
+    /// Serializes the header, computing a fresh checksum. The stored
+    /// [`DynamicHeader::checksum`] is ignored.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..8].copy_from_slice(COOKIE);
+        bytes[DATA_OFFSET..DATA_OFFSET + 8].copy_from_slice(&self.data_offset.to_be_bytes());
+        bytes[TABLE_OFFSET..TABLE_OFFSET + 8].copy_from_slice(&self.table_offset.to_be_bytes());
+        bytes[HEADER_VERSION_OFFSET..HEADER_VERSION_OFFSET + 4]
+            .copy_from_slice(&self.header_version.to_be_bytes());
+        bytes[MAX_TABLE_ENTRIES..MAX_TABLE_ENTRIES + 4]
+            .copy_from_slice(&self.max_table_entries.to_be_bytes());
+        bytes[BLOCK_SIZE..BLOCK_SIZE + 4].copy_from_slice(&self.block_size.to_be_bytes());
+        bytes[PARENT_UNIQUE_ID..PARENT_UNIQUE_ID + 16]
+            .copy_from_slice(&self.parent_unique_id.to_bytes_le());
+        bytes[PARENT_TIMESTAMP..PARENT_TIMESTAMP + 4]
+            .copy_from_slice(&self.parent_timestamp.to_be_bytes());
+
+        // names longer than the field are truncated
+        let name = &mut bytes[PARENT_NAME..PARENT_NAME + PARENT_NAME_SIZE];
+        for (unit, bytes) in self
+            .parent_name
+            .encode_utf16()
+            .zip(name.chunks_exact_mut(2))
+        {
+            bytes.copy_from_slice(&unit.to_be_bytes());
+        }
+
+        for (index, locator) in self.parent_locators.iter().enumerate() {
+            let start = PARENT_LOCATORS + index * LOCATOR_SIZE;
+            bytes[start..start + LOCATOR_SIZE].copy_from_slice(&locator.to_bytes());
+        }
+
+        let checksum = checksum(&bytes, CHECKSUM);
+        bytes[CHECKSUM..CHECKSUM + 4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    /// Returns the number of sectors in a block.
+    pub fn sectors_per_block(&self) -> u64 {
+        u64::from(self.block_size) / SECTOR_SIZE
+    }
+
+    /// Returns the size in bytes of the sector bitmap that precedes every block, padded to a
+    /// sector boundary.
+    pub fn bitmap_size(&self) -> u64 {
+        self.sectors_per_block()
+            .div_ceil(8)
+            .next_multiple_of(SECTOR_SIZE)
+    }
+
+    /// Returns the size in bytes of the Block Allocation Table, padded to a sector boundary.
+    pub fn table_size(&self) -> u64 {
+        (u64::from(self.max_table_entries) * 4).next_multiple_of(SECTOR_SIZE)
+    }
+}
+
+/// Parses the Block Allocation Table in `bytes`: one big-endian sector offset per block, or
+/// [`UNUSED_ENTRY`] for blocks that are not allocated.
+pub(crate) fn parse_bat(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|entry| u32::from_be_bytes(entry.try_into().unwrap()))
+        .collect()
+}
+
+/// Returns whether the bit of `sector` is set in a sector bitmap. Bits are ordered from the
+/// most significant bit of the first byte.
+pub(crate) fn bitmap_bit(bitmap: &[u8], sector: u64) -> bool {
+    bitmap[(sector / 8) as usize] & (0x80 >> (sector % 8)) != 0
+}
+
+/// Sets the bit of `sector` in a sector bitmap.
+pub(crate) fn set_bitmap_bit(bitmap: &mut [u8], sector: u64) {
+    bitmap[(sector / 8) as usize] |= 0x80 >> (sector % 8);
+}
+
+/// Splits the byte range `start..start + len` of a block into runs of sectors whose bits in
+/// `bitmap` are equal. Returns the start, the length and the bit of every run.
+pub(crate) fn bitmap_runs(bitmap: &[u8], start: u64, len: u64) -> Vec<(u64, u64, bool)> {
+    let end = start + len;
+    let mut runs: Vec<(u64, u64, bool)> = Vec::new();
+    let mut position = start;
+
+    while position < end {
+        let sector = position / SECTOR_SIZE;
+        let run_end = ((sector + 1) * SECTOR_SIZE).min(end);
+        let bit = bitmap_bit(bitmap, sector);
+
+        match runs.last_mut() {
+            Some((_, run_len, run_bit)) if *run_bit == bit => *run_len += run_end - position,
+            _ => runs.push((position, run_end - position, bit)),
+        }
+        position = run_end;
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> DynamicHeader {
+        DynamicHeader {
+            data_offset: u64::MAX,
+            table_offset: 1536,
+            header_version: HEADER_VERSION,
+            max_table_entries: 32,
+            block_size: 2 << 20,
+            checksum: 0,
+            parent_unique_id: Uuid::nil(),
+            parent_timestamp: 0,
+            parent_name: String::new(),
+            parent_locators: Default::default(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = header().to_bytes();
+        let parsed = DynamicHeader::parse(&bytes, 512).unwrap();
+        assert_eq!(
+            DynamicHeader {
+                checksum: 0,
+                ..parsed.clone()
+            },
+            header()
+        );
+        assert_eq!(parsed.sectors_per_block(), 4096);
+        assert_eq!(parsed.bitmap_size(), 512);
+        assert_eq!(parsed.table_size(), 512);
+    }
+
+    #[test]
+    fn parent_fields_round_trip() {
+        let mut header = header();
+        header.parent_unique_id = Uuid::from_u128(0x0011_2233_4455_6677_8899_aabb_ccdd_eeff);
+        header.parent_timestamp = 0x1234_5678;
+        header.parent_name = "pärent.vhd".to_owned();
+        header.parent_locators[1] = ParentLocator {
+            platform_code: PlatformCode::W2ru,
+            data_space: 512,
+            data_length: 28,
+            data_offset: 0x800,
+        };
+
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[PARENT_NAME..PARENT_NAME + 4], &[0, b'p', 0, 0xe4]);
+        assert_eq!(&bytes[PARENT_LOCATORS + 24..PARENT_LOCATORS + 28], b"W2ru");
+
+        let parsed = DynamicHeader::parse(&bytes, 512).unwrap();
+        assert_eq!(parsed.parent_unique_id, header.parent_unique_id);
+        assert_eq!(parsed.parent_timestamp, 0x1234_5678);
+        assert_eq!(parsed.parent_name, "pärent.vhd");
+        assert_eq!(parsed.parent_locators, header.parent_locators);
+    }
+
+    #[test]
+    fn unknown_platform_code() {
+        let mut bytes = header().to_bytes();
+        bytes[PARENT_LOCATORS + 48..PARENT_LOCATORS + 52].copy_from_slice(b"Lnx ");
+        let checksum = checksum(&bytes, CHECKSUM);
+        bytes[CHECKSUM..CHECKSUM + 4].copy_from_slice(&checksum.to_be_bytes());
+
+        let error = DynamicHeader::parse(&bytes, 512).unwrap_err();
+        assert_eq!(error.structure(), Structure::ParentLocator);
+        assert_eq!(error.offset(), 512 + PARENT_LOCATORS as u64 + 48);
+    }
+
+    #[test]
+    fn decode_locator_paths() {
+        let locator = |platform_code| ParentLocator {
+            platform_code,
+            ..Default::default()
+        };
+        let utf16: Vec<u8> = ".\\parent.vhd"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .chain([0, 0])
+            .collect();
+
+        assert_eq!(
+            locator(PlatformCode::W2ru).decode_path(&utf16).as_deref(),
+            Some(".\\parent.vhd")
+        );
+        assert_eq!(
+            locator(PlatformCode::Wi2k)
+                .decode_path(b"C:\\parent.vhd\0")
+                .as_deref(),
+            Some("C:\\parent.vhd")
+        );
+        assert_eq!(
+            locator(PlatformCode::MacX)
+                .decode_path(b"file://localhost/Users/parent.vhd")
+                .as_deref(),
+            Some("/Users/parent.vhd")
+        );
+        assert_eq!(locator(PlatformCode::Mac).decode_path(b"alias"), None);
+        assert_eq!(locator(PlatformCode::W2ku).decode_path(&[0, 0]), None);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut bytes = header().to_bytes();
+        bytes[TABLE_OFFSET + 7] ^= 1;
+        let error = DynamicHeader::parse(&bytes, 512).unwrap_err();
+        assert_eq!(error.structure(), Structure::DynamicHeader);
+        assert_eq!(error.field(), "checksum");
+        assert_eq!(error.offset(), 512 + CHECKSUM as u64);
+    }
+
+    #[test]
+    fn bad_block_size() {
+        let mut header = header();
+        header.block_size = 3 << 20;
+        let error = DynamicHeader::parse(&header.to_bytes(), 512).unwrap_err();
+        assert_eq!(error.field(), "block size");
+        assert_eq!(error.found(), Some("3145728"));
+    }
+
+    #[test]
+    fn small_block_bitmap() {
+        let mut header = header();
+        header.block_size = 4096;
+        assert_eq!(header.bitmap_size(), 512);
+    }
+
+    #[test]
+    fn runs() {
+        let bitmap = [0b1100_0000];
+        assert_eq!(
+            bitmap_runs(&bitmap, 100, 1500),
+            [(100, 924, true), (1024, 576, false)]
+        );
+        assert_eq!(bitmap_runs(&bitmap, 1024, 10), [(1024, 10, false)]);
+        assert!(bitmap_runs(&bitmap, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn bitmap_bits() {
+        let bitmap = [0b1000_0001, 0b0100_0000];
+        assert!(bitmap_bit(&bitmap, 0));
+        assert!(!bitmap_bit(&bitmap, 1));
+        assert!(bitmap_bit(&bitmap, 7));
+        assert!(bitmap_bit(&bitmap, 9));
+        assert!(!bitmap_bit(&bitmap, 15));
+
+        let mut bitmap = [0; 2];
+        set_bitmap_bit(&mut bitmap, 0);
+        set_bitmap_bit(&mut bitmap, 9);
+        assert_eq!(bitmap, [0b1000_0000, 0b0100_0000]);
+    }
+}
@@ -0,0 +1,429 @@
+use std::fmt::{self, Display};
+
+use uuid::Uuid;
+
+use super::{be_u16, be_u32, be_u64, checksum};
+use crate::{ParseError, Structure};
+
+/// Cookie that starts every footer.
+pub(crate) const COOKIE: &[u8; 8] = b"conectix";
+/// Size of the footer in bytes.
+pub(crate) const FOOTER_SIZE: usize = 512;
+/// Version 1.0 of the format, the only one defined by the specification.
+pub(crate) const FORMAT_VERSION: u32 = 0x0001_0000;
+
+const FEATURES: usize = 8;
+const FORMAT_VERSION_OFFSET: usize = 12;
+const DATA_OFFSET: usize = 16;
+const TIMESTAMP: usize = 24;
+const CREATOR_APPLICATION: usize = 28;
+const CREATOR_VERSION: usize = 32;
+const CREATOR_HOST_OS: usize = 36;
+const ORIGINAL_SIZE: usize = 40;
+const CURRENT_SIZE: usize = 48;
+const GEOMETRY: usize = 56;
+const DISK_TYPE: usize = 60;
+const CHECKSUM: usize = 64;
+const UNIQUE_ID: usize = 68;
+const SAVED_STATE: usize = 84;
+
+/// The type of a VHD, as stored in the footer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiskType {
+    /// The footer does not specify a type.
+    None,
+    /// The virtual disk is the data preceding the footer.
+    Fixed,
+    /// Blocks of the virtual disk are allocated on demand.
+    Dynamic,
+    /// Like [`DiskType::Dynamic`], with unallocated sectors read from a parent disk.
+    Differencing,
+}
+
+impl DiskType {
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(DiskType::None),
+            2 => Some(DiskType::Fixed),
+            3 => Some(DiskType::Dynamic),
+            4 => Some(DiskType::Differencing),
+            _ => None,
+        }
+    }
+
+    fn to_u32(self) -> u32 {
+        match self {
+            DiskType::None => 0,
+            DiskType::Fixed => 2,
+            DiskType::Dynamic => 3,
+            DiskType::Differencing => 4,
+        }
+    }
+}
+
+impl Display for DiskType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DiskType::None => "none",
+            DiskType::Fixed => "fixed",
+            DiskType::Dynamic => "dynamic",
+            DiskType::Differencing => "differencing",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The cylinder/heads/sectors-per-track geometry of a VHD.
+///
+/// Virtual PC and the emulated IDE controller of Hyper-V derive the disk size from the geometry
+/// rather than from [`VhdFooter::current_size`], so it must be computed with the algorithm of
+/// the specification ([`DiskGeometry::from_disk_size`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DiskGeometry {
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors_per_track: u8,
+}
+
+impl DiskGeometry {
+    /// Computes the geometry of a disk of `size` bytes with the algorithm of the specification.
+    ///
+    /// The geometry addresses at most `size` bytes, usually slightly less; see
+    /// [`DiskGeometry::capacity`]. Disks larger than 65535 × 16 × 255 sectors (about 127 GiB)
+    /// get that largest geometry.
+    pub fn from_disk_size(size: u64) -> Self {
+        let total_sectors = (size / 512).min(65535 * 16 * 255);
+
+        let (sectors_per_track, heads, cylinder_times_heads) = if total_sectors >= 65535 * 16 * 63 {
+            (255, 16, total_sectors / 255)
+        } else {
+            let mut sectors_per_track = 17;
+            let mut cylinder_times_heads = total_sectors / sectors_per_track;
+            let mut heads = cylinder_times_heads.div_ceil(1024).max(4);
+
+            if cylinder_times_heads >= heads * 1024 || heads > 16 {
+                sectors_per_track = 31;
+                heads = 16;
+                cylinder_times_heads = total_sectors / sectors_per_track;
+            }
+            if cylinder_times_heads >= heads * 1024 {
+                sectors_per_track = 63;
+                heads = 16;
+                cylinder_times_heads = total_sectors / sectors_per_track;
+            }
+            (sectors_per_track, heads, cylinder_times_heads)
+        };
+
+        DiskGeometry {
+            cylinders: (cylinder_times_heads / heads) as u16,
+            heads: heads as u8,
+            sectors_per_track: sectors_per_track as u8,
+        }
+    }
+
+    /// Returns the number of sectors the geometry addresses.
+    pub fn total_sectors(&self) -> u64 {
+        u64::from(self.cylinders) * u64::from(self.heads) * u64::from(self.sectors_per_track)
+    }
+
+    /// Returns the capacity in bytes the geometry addresses: the disk size as seen by software
+    /// that relies on the geometry.
+    pub fn capacity(&self) -> u64 {
+        self.total_sectors() * 512
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        DiskGeometry {
+            cylinders: be_u16(bytes, 0),
+            heads: bytes[2],
+            sectors_per_track: bytes[3],
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 4] {
+        let [c0, c1] = self.cylinders.to_be_bytes();
+        [c0, c1, self.heads, self.sectors_per_track]
+    }
+}
+
+/// The 512-byte footer at the end of every VHD file.
+///
+/// Dynamic and differencing disks also keep a copy of it at offset 0. All fields are stored
+/// big-endian; the unique ID is stored in the GUID layout Windows uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdFooter {
+    /// Feature flags; bit 0 marks a temporary disk, bit 1 is reserved and always set.
+    pub features: u32,
+    /// Format version, major version in the high 16 bits.
+    pub format_version: u32,
+    /// Absolute offset of the dynamic disk header, [`u64::MAX`] for fixed disks.
+    pub data_offset: u64,
+    /// Seconds since January 1, 2000 12:00:00 AM UTC at which the image was last written.
+    pub timestamp: u32,
+    /// Four-character code of the application that created the image, e.g. `vpc ` or `win `.
+    pub creator_application: [u8; 4],
+    /// Version of the creator application, major version in the high 16 bits.
+    pub creator_version: u32,
+    /// Host OS the image was created on, e.g. `Wi2k` (`0x5769326b`).
+    pub creator_host_os: u32,
+    /// Size of the virtual disk at creation, in bytes.
+    pub original_size: u64,
+    /// Current size of the virtual disk, in bytes.
+    pub current_size: u64,
+    pub geometry: DiskGeometry,
+    pub disk_type: DiskType,
+    /// The checksum stored in the footer. [`VhdFooter::to_bytes`] recomputes it.
+    pub checksum: u32,
+    pub unique_id: Uuid,
+    /// Whether the disk is in a saved state (the VM was hibernated).
+    pub saved_state: bool,
+}
+
+impl VhdFooter {
+    /// Parses the footer in `bytes`, which was read from file offset `offset`.
+    ///
+    /// # Errors
+    /// If the cookie, checksum, format version or disk type is invalid.
+    pub fn parse(bytes: &[u8; FOOTER_SIZE], offset: u64) -> Result<Self, ParseError> {
+        let field_offset = |field: usize| offset + field as u64;
+
+        if &bytes[..8] != COOKIE {
+            return Err(ParseError::mismatch(
+                Structure::Footer,
+                "cookie",
+                offset,
+                "\"conectix\"",
+                format!("{:?}", String::from_utf8_lossy(&bytes[..8])),
+            ));
+        }
+
+        let stored_checksum = be_u32(bytes, CHECKSUM);
+        let computed_checksum = checksum(bytes, CHECKSUM);
+        if stored_checksum != computed_checksum {
+            return Err(ParseError::mismatch(
+                Structure::Footer,
+                "checksum",
+                field_offset(CHECKSUM),
+                format!("{computed_checksum:#010x}"),
+                format!("{stored_checksum:#010x}"),
+            ));
+        }
+
+        let format_version = be_u32(bytes, FORMAT_VERSION_OFFSET);
+        if format_version >> 16 != FORMAT_VERSION >> 16 {
+            return Err(ParseError::mismatch(
+                Structure::Footer,
+                "format version",
+                field_offset(FORMAT_VERSION_OFFSET),
+                format!("{FORMAT_VERSION:#010x}"),
+                format!("{format_version:#010x}"),
+            ));
+        }
+
+        let disk_type = be_u32(bytes, DISK_TYPE);
+        let disk_type = DiskType::from_u32(disk_type).ok_or_else(|| {
+            ParseError::new(Structure::Footer, "disk type", field_offset(DISK_TYPE))
+                .with_expected("0, 2, 3 or 4")
+                .with_found(disk_type)
+        })?;
+
+        Ok(VhdFooter {
+            features: be_u32(bytes, FEATURES),
+            format_version,
+            data_offset: be_u64(bytes, DATA_OFFSET),
+            timestamp: be_u32(bytes, TIMESTAMP),
+            creator_application: bytes[CREATOR_APPLICATION..CREATOR_APPLICATION + 4]
+                .try_into()
+                .unwrap(),
+            creator_version: be_u32(bytes, CREATOR_VERSION),
+            creator_host_os: be_u32(bytes, CREATOR_HOST_OS),
+            original_size: be_u64(bytes, ORIGINAL_SIZE),
+            current_size: be_u64(bytes, CURRENT_SIZE),
+            geometry: DiskGeometry::from_bytes(&bytes[GEOMETRY..GEOMETRY + 4]),
+            disk_type,
+            checksum: stored_checksum,
+            unique_id: Uuid::from_bytes_le(bytes[UNIQUE_ID..UNIQUE_ID + 16].try_into().unwrap()),
+            saved_state: bytes[SAVED_STATE] != 0,
+        })
+    }
+
+    /// Returns whether the stored geometry is the one the specification computes for
+    /// [`VhdFooter::current_size`].
+    ///
+    /// A mismatch, often left behind by conversion tools that resize the disk without updating
+    /// the geometry, makes the disk appear smaller or larger than `current_size` to software
+    /// that relies on the geometry.
+    pub fn geometry_matches_size(&self) -> bool {
+        self.geometry == DiskGeometry::from_disk_size(self.current_size)
+    }
+
+    /// Serializes the footer, computing a fresh checksum. The stored [`VhdFooter::checksum`]
+    /// is ignored.
+    pub fn to_bytes(&self) -> [u8; FOOTER_SIZE] {
+        let mut bytes = [0; FOOTER_SIZE];
+        bytes[..8].copy_from_slice(COOKIE);
+        bytes[FEATURES..FEATURES + 4].copy_from_slice(&self.features.to_be_bytes());
+        bytes[FORMAT_VERSION_OFFSET..FORMAT_VERSION_OFFSET + 4]
+            .copy_from_slice(&self.format_version.to_be_bytes());
+        bytes[DATA_OFFSET..DATA_OFFSET + 8].copy_from_slice(&self.data_offset.to_be_bytes());
+        bytes[TIMESTAMP..TIMESTAMP + 4].copy_from_slice(&self.timestamp.to_be_bytes());
+        bytes[CREATOR_APPLICATION..CREATOR_APPLICATION + 4]
+            .copy_from_slice(&self.creator_application);
+        bytes[CREATOR_VERSION..CREATOR_VERSION + 4]
+            .copy_from_slice(&self.creator_version.to_be_bytes());
+        bytes[CREATOR_HOST_OS..CREATOR_HOST_OS + 4]
+            .copy_from_slice(&self.creator_host_os.to_be_bytes());
+        bytes[ORIGINAL_SIZE..ORIGINAL_SIZE + 8].copy_from_slice(&self.original_size.to_be_bytes());
+        bytes[CURRENT_SIZE..CURRENT_SIZE + 8].copy_from_slice(&self.current_size.to_be_bytes());
+        bytes[GEOMETRY..GEOMETRY + 4].copy_from_slice(&self.geometry.to_bytes());
+        bytes[DISK_TYPE..DISK_TYPE + 4].copy_from_slice(&self.disk_type.to_u32().to_be_bytes());
+        bytes[UNIQUE_ID..UNIQUE_ID + 16].copy_from_slice(&self.unique_id.to_bytes_le());
+        bytes[SAVED_STATE] = u8::from(self.saved_state);
+
+        let checksum = checksum(&bytes, CHECKSUM);
+        bytes[CHECKSUM..CHECKSUM + 4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn footer() -> VhdFooter {
+        VhdFooter {
+            features: 2,
+            format_version: FORMAT_VERSION,
+            data_offset: 512,
+            timestamp: 0x2c4c_6a5b,
+            creator_application: *b"win ",
+            creator_version: 0x000a_0000,
+            creator_host_os: 0x5769_326b,
+            original_size: 64 << 20,
+            current_size: 64 << 20,
+            geometry: DiskGeometry {
+                cylinders: 963,
+                heads: 8,
+                sectors_per_track: 17,
+            },
+            disk_type: DiskType::Dynamic,
+            checksum: 0,
+            unique_id: Uuid::from_u128(0x0011_2233_4455_6677_8899_aabb_ccdd_eeff),
+            saved_state: false,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = footer().to_bytes();
+        let parsed = VhdFooter::parse(&bytes, 0).unwrap();
+        assert_eq!(parsed.checksum, checksum(&bytes, CHECKSUM));
+        assert_eq!(
+            VhdFooter {
+                checksum: 0,
+                ..parsed
+            },
+            footer()
+        );
+    }
+
+    #[test]
+    fn layout() {
+        let bytes = footer().to_bytes();
+        assert_eq!(&bytes[..8], b"conectix");
+        assert_eq!(&bytes[28..32], b"win ");
+        assert_eq!(&bytes[36..40], b"Wi2k");
+        assert_eq!(&bytes[56..60], &[0x03, 0xc3, 8, 17]);
+        assert_eq!(be_u32(&bytes, 60), 3);
+        // the unique ID is stored in the GUID layout
+        assert_eq!(&bytes[68..72], &[0x33, 0x22, 0x11, 0x00]);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut bytes = footer().to_bytes();
+        bytes[CURRENT_SIZE + 7] ^= 1;
+        let error = VhdFooter::parse(&bytes, 0x1000).unwrap_err();
+        assert_eq!(error.structure(), Structure::Footer);
+        assert_eq!(error.field(), "checksum");
+        assert_eq!(error.offset(), 0x1000 + CHECKSUM as u64);
+        assert_eq!(
+            error.found(),
+            Some(format!("{:#010x}", checksum(&footer().to_bytes(), CHECKSUM)).as_str())
+        );
+    }
+
+    #[test]
+    fn bad_cookie() {
+        let mut bytes = footer().to_bytes();
+        bytes[..8].copy_from_slice(b"cxsparse");
+        let error = VhdFooter::parse(&bytes, 0).unwrap_err();
+        assert_eq!(error.field(), "cookie");
+        assert_eq!(error.found(), Some("\"cxsparse\""));
+    }
+
+    #[test]
+    fn bad_disk_type() {
+        let mut footer = footer();
+        footer.disk_type = DiskType::Fixed;
+        let mut bytes = footer.to_bytes();
+        bytes[DISK_TYPE + 3] = 5;
+        let fixed = checksum(&bytes, CHECKSUM);
+        bytes[CHECKSUM..CHECKSUM + 4].copy_from_slice(&fixed.to_be_bytes());
+
+        let error = VhdFooter::parse(&bytes, 0).unwrap_err();
+        assert_eq!(error.field(), "disk type");
+        assert_eq!(error.offset(), DISK_TYPE as u64);
+        assert_eq!(error.found(), Some("5"));
+    }
+
+    #[test]
+    fn geometry_from_disk_size() {
+        let geometry = |size| {
+            let geometry = DiskGeometry::from_disk_size(size);
+            (
+                geometry.cylinders,
+                geometry.heads,
+                geometry.sectors_per_track,
+            )
+        };
+        assert_eq!(geometry(64 << 20), (963, 8, 17));
+        assert_eq!(geometry(1 << 30), (2080, 16, 63));
+        assert_eq!(geometry(127 << 30), (65278, 16, 255));
+        assert_eq!(geometry(2 << 40), (65535, 16, 255));
+    }
+
+    #[test]
+    fn geometry_capacity() {
+        let geometry = DiskGeometry::from_disk_size(64 << 20);
+        assert_eq!(geometry.total_sectors(), 963 * 8 * 17);
+        assert_eq!(geometry.capacity(), 67_055_616);
+        assert!(geometry.capacity() <= 64 << 20);
+
+        // the capacity of a geometry is addressed by the same geometry
+        assert_eq!(DiskGeometry::from_disk_size(geometry.capacity()), geometry);
+
+        let largest = DiskGeometry::from_disk_size(1 << 40);
+        assert_eq!(largest.capacity(), 65535 * 16 * 255 * 512);
+    }
+
+    #[test]
+    fn geometry_mismatch() {
+        let mut footer = footer();
+        assert!(footer.geometry_matches_size());
+
+        // the disk was enlarged without updating the geometry
+        footer.current_size = 128 << 20;
+        assert!(!footer.geometry_matches_size());
+        footer.geometry = DiskGeometry::from_disk_size(128 << 20);
+        assert!(footer.geometry_matches_size());
+    }
+
+    #[test]
+    fn unsupported_format_version() {
+        let mut footer = footer();
+        footer.format_version = 0x0002_0000;
+        let error = VhdFooter::parse(&footer.to_bytes(), 0).unwrap_err();
+        assert_eq!(error.field(), "format version");
+    }
+}
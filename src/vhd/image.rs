@@ -0,0 +1,1002 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::dynamic::{
+    bitmap_bit, bitmap_runs, parse_bat, set_bitmap_bit, HEADER_SIZE, SECTOR_SIZE, UNUSED_ENTRY,
+};
+use super::footer::FOOTER_SIZE;
+use super::{DiskType, DynamicHeader, PlatformCode, VhdFooter};
+use crate::{Error, ErrorKind, OpenMode, ParseError, Result, Structure};
+
+/// Maximum number of disks in a differencing chain, which guards against circular chains.
+const MAX_CHAIN_DEPTH: usize = 64;
+
+/// Platform codes of the parent locators, in the order their paths are tried.
+const LOCATOR_ORDER: [PlatformCode; 5] = [
+    PlatformCode::W2ru,
+    PlatformCode::Wi2r,
+    PlatformCode::W2ku,
+    PlatformCode::Wi2k,
+    PlatformCode::MacX,
+];
+
+/// A VHD file opened with the pure-Rust implementation of the format.
+///
+/// Implements [`Read`], [`Write`] and [`Seek`] over the virtual disk, which is
+/// [`VhdImage::virtual_size`] bytes long. Unallocated blocks and sectors of dynamic disks read
+/// as zeros; those of differencing disks are read from the parent disk. Writing to an
+/// unallocated block allocates it at the end of the file; only the written sectors of a
+/// differencing disk stop reading from the parent.
+#[derive(Debug)]
+pub struct VhdImage {
+    file: File,
+    path: PathBuf,
+    mode: OpenMode,
+    footer: VhdFooter,
+    dynamic: Option<Dynamic>,
+    position: u64,
+}
+
+/// The structures of a dynamic or differencing disk.
+#[derive(Debug)]
+struct Dynamic {
+    header: DynamicHeader,
+    bat: Vec<u32>,
+    parent: Option<Box<VhdImage>>,
+    /// Offset of the trailing footer, where the next block is allocated.
+    footer_offset: u64,
+}
+
+impl VhdImage {
+    /// Opens the VHD file at `path` and validates its footer.
+    ///
+    /// The parent of a differencing disk is opened read-only. It is looked up through the parent
+    /// locators (relative paths first), then by the parent name in the directory of the child,
+    /// and must carry the unique ID recorded in the child.
+    ///
+    /// # Errors
+    /// If the file cannot be opened, its structures are invalid ([`ErrorKind::Corrupt`]) or the
+    /// disk type is not supported ([`ErrorKind::Unsupported`]). For differencing disks, also if
+    /// the parent cannot be found ([`ErrorKind::NotFound`]), has another unique ID
+    /// ([`ErrorKind::Corrupt`]) or cannot be opened.
+    pub fn open<P: AsRef<Path>>(path: P, open_mode: OpenMode) -> Result<Self> {
+        let path = path.as_ref();
+        Self::open_file(path, open_mode, 0).map_err(|e| e.with_path(path))
+    }
+
+    /// Opens the image at `path`, the `depth`th disk of a differencing chain.
+    fn open_file(path: &Path, open_mode: OpenMode, depth: usize) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(matches!(open_mode, OpenMode::ReadWrite))
+            .open(path)
+            .map_err(|e| Error::io("open", e))?;
+
+        let file_size = file.metadata().map_err(|e| Error::io("open", e))?.len();
+        if file_size < FOOTER_SIZE as u64 {
+            return Err(Error::corrupt(
+                "open",
+                ParseError::mismatch(
+                    Structure::Footer,
+                    "file size",
+                    0,
+                    "at least 512 bytes",
+                    file_size,
+                ),
+            ));
+        }
+
+        let footer_offset = file_size - FOOTER_SIZE as u64;
+        let mut bytes = [0; FOOTER_SIZE];
+        read_exact_at(&file, footer_offset, &mut bytes).map_err(|e| Error::io("open", e))?;
+        let footer =
+            VhdFooter::parse(&bytes, footer_offset).map_err(|e| Error::corrupt("open", e))?;
+
+        let dynamic = match footer.disk_type {
+            DiskType::Fixed => {
+                if footer.current_size > footer_offset {
+                    return Err(Error::corrupt(
+                        "open",
+                        ParseError::mismatch(
+                            Structure::Footer,
+                            "current size",
+                            footer_offset + 48,
+                            format!("at most {footer_offset}"),
+                            footer.current_size,
+                        ),
+                    ));
+                }
+                None
+            }
+            DiskType::Dynamic | DiskType::Differencing => {
+                let mut dynamic = Dynamic::read(&file, &footer, footer_offset)
+                    .map_err(|e| e.into_error("open"))?;
+                if footer.disk_type == DiskType::Differencing {
+                    let parent = open_parent(path, &file, &dynamic.header, depth)?;
+                    dynamic.parent = Some(Box::new(parent));
+                }
+                Some(dynamic)
+            }
+            disk_type => {
+                return Err(Error::new(ErrorKind::Unsupported, "open")
+                    .with_source(format!("{disk_type} VHDs are not supported")));
+            }
+        };
+
+        Ok(VhdImage {
+            file,
+            path: path.to_path_buf(),
+            mode: open_mode,
+            footer,
+            dynamic,
+            position: 0,
+        })
+    }
+
+    /// Returns the path the image was opened from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the mode the image was opened in.
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+
+    /// Returns the footer of the image.
+    pub fn footer(&self) -> &VhdFooter {
+        &self.footer
+    }
+
+    /// Returns the dynamic disk header, or `None` for fixed disks.
+    pub fn dynamic_header(&self) -> Option<&DynamicHeader> {
+        self.dynamic.as_ref().map(|dynamic| &dynamic.header)
+    }
+
+    /// Returns the Block Allocation Table, or `None` for fixed disks. Each entry is the sector
+    /// offset of a block, or `u32::MAX` if the block is not allocated.
+    pub fn bat(&self) -> Option<&[u32]> {
+        self.dynamic.as_ref().map(|dynamic| dynamic.bat.as_slice())
+    }
+
+    /// Returns the parent of a differencing disk.
+    pub fn parent(&self) -> Option<&VhdImage> {
+        self.dynamic.as_ref()?.parent.as_deref()
+    }
+
+    /// Returns the block size in bytes, or 0 for fixed disks.
+    pub fn block_size(&self) -> u32 {
+        self.dynamic_header().map_or(0, |header| header.block_size)
+    }
+
+    /// Returns the size of the virtual disk in bytes.
+    pub fn virtual_size(&self) -> u64 {
+        self.footer.current_size
+    }
+
+    /// Returns the size of the image file in bytes.
+    ///
+    /// # Errors
+    /// If the file metadata cannot be read.
+    pub fn physical_size(&self) -> Result<u64> {
+        self.file
+            .metadata()
+            .map(|metadata| metadata.len())
+            .map_err(|e| Error::io("get size", e).with_path(&self.path))
+    }
+
+    /// Reads from the virtual disk at `offset`, returning the number of bytes read. Stops at the
+    /// end of the virtual disk.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.virtual_size().saturating_sub(offset);
+        let len = (buf.len() as u64).min(remaining) as usize;
+        let buf = &mut buf[..len];
+
+        match &self.dynamic {
+            // the virtual disk of a fixed VHD is the data preceding the footer
+            None => read_exact_at(&self.file, offset, buf)?,
+            Some(dynamic) => dynamic.read_at(&self.file, offset, buf)?,
+        }
+        Ok(len)
+    }
+
+    /// Writes to the virtual disk at `offset`, returning the number of bytes written. Stops at
+    /// the end of the virtual disk.
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<usize> {
+        if matches!(self.mode, OpenMode::ReadOnly) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the image is opened read-only",
+            ));
+        }
+
+        let remaining = self.virtual_size().saturating_sub(offset);
+        let len = (buf.len() as u64).min(remaining) as usize;
+        let buf = &buf[..len];
+
+        match &mut self.dynamic {
+            None => write_all_at(&self.file, offset, buf)?,
+            Some(dynamic) => dynamic.write_at(&self.file, &self.footer, offset, buf)?,
+        }
+        Ok(len)
+    }
+}
+
+impl Dynamic {
+    /// Reads the dynamic disk header and the BAT, checking that they and every allocated block
+    /// lie before the footer at `footer_offset`.
+    fn read(file: &File, footer: &VhdFooter, footer_offset: u64) -> ReadResult<Self> {
+        let header_offset = footer.data_offset;
+        if header_offset.saturating_add(HEADER_SIZE as u64) > footer_offset {
+            return Err(ParseError::mismatch(
+                Structure::Footer,
+                "data offset",
+                footer_offset + 16,
+                format!(
+                    "at most {}",
+                    footer_offset.saturating_sub(HEADER_SIZE as u64)
+                ),
+                header_offset,
+            )
+            .into());
+        }
+
+        let mut bytes = [0; HEADER_SIZE];
+        read_exact_at(file, header_offset, &mut bytes)?;
+        let header = DynamicHeader::parse(&bytes, header_offset)?;
+
+        let capacity = u64::from(header.max_table_entries) * u64::from(header.block_size);
+        if capacity < footer.current_size {
+            return Err(ParseError::mismatch(
+                Structure::DynamicHeader,
+                "max table entries",
+                header_offset + 28,
+                format!(
+                    "at least {}",
+                    footer.current_size.div_ceil(u64::from(header.block_size))
+                ),
+                header.max_table_entries,
+            )
+            .into());
+        }
+
+        if header.table_offset.saturating_add(header.table_size()) > footer_offset {
+            return Err(ParseError::mismatch(
+                Structure::DynamicHeader,
+                "table offset",
+                header_offset + 16,
+                format!(
+                    "at most {}",
+                    footer_offset.saturating_sub(header.table_size())
+                ),
+                header.table_offset,
+            )
+            .into());
+        }
+
+        for (index, locator) in header.parent_locators.iter().enumerate() {
+            let data_end = locator
+                .data_offset
+                .saturating_add(u64::from(locator.data_length));
+            if locator.platform_code != PlatformCode::None && data_end > footer_offset {
+                return Err(ParseError::mismatch(
+                    Structure::ParentLocator,
+                    "platform data offset",
+                    header_offset + 576 + index as u64 * 24 + 16,
+                    format!(
+                        "at most {}",
+                        footer_offset.saturating_sub(u64::from(locator.data_length))
+                    ),
+                    locator.data_offset,
+                )
+                .into());
+            }
+        }
+
+        let mut bytes = vec![0; header.max_table_entries as usize * 4];
+        read_exact_at(file, header.table_offset, &mut bytes)?;
+        let bat = parse_bat(&bytes);
+
+        let block_span = header.bitmap_size() + u64::from(header.block_size);
+        for (index, &entry) in bat.iter().enumerate() {
+            if entry != UNUSED_ENTRY && u64::from(entry) * SECTOR_SIZE + block_span > footer_offset
+            {
+                return Err(ParseError::mismatch(
+                    Structure::BatEntry,
+                    "block sector offset",
+                    header.table_offset + index as u64 * 4,
+                    format!(
+                        "at most {}",
+                        footer_offset.saturating_sub(block_span) / SECTOR_SIZE
+                    ),
+                    entry,
+                )
+                .into());
+            }
+        }
+
+        Ok(Dynamic {
+            header,
+            bat,
+            parent: None,
+            footer_offset: footer_offset.next_multiple_of(SECTOR_SIZE),
+        })
+    }
+
+    /// Reads `buf.len()` bytes of the virtual disk at `offset`, block by block.
+    fn read_at(&self, file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let block_size = u64::from(self.header.block_size);
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let block = (position / block_size) as usize;
+            let start = position % block_size;
+            let len = (buf.len() - done).min((block_size - start) as usize);
+            let chunk = &mut buf[done..done + len];
+
+            match self.bat[block] {
+                UNUSED_ENTRY => self.read_parent(position, chunk)?,
+                entry => {
+                    let block_offset = u64::from(entry) * SECTOR_SIZE;
+                    let mut bitmap = vec![0; self.header.bitmap_size() as usize];
+                    read_exact_at(file, block_offset, &mut bitmap)?;
+
+                    let data_offset = block_offset + self.header.bitmap_size();
+                    for (run_start, run_len, present) in bitmap_runs(&bitmap, start, len as u64) {
+                        let run = &mut chunk
+                            [(run_start - start) as usize..(run_start - start + run_len) as usize];
+                        if present {
+                            read_exact_at(file, data_offset + run_start, run)?;
+                        } else {
+                            self.read_parent(position - start + run_start, run)?;
+                        }
+                    }
+                }
+            }
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf` to the virtual disk at `offset`, block by block, allocating the blocks that
+    /// are not allocated yet.
+    fn write_at(
+        &mut self,
+        file: &File,
+        footer: &VhdFooter,
+        offset: u64,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        let block_size = u64::from(self.header.block_size);
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let block = (position / block_size) as usize;
+            let start = position % block_size;
+            let len = (buf.len() - done).min((block_size - start) as usize);
+
+            let block_offset = match self.bat[block] {
+                UNUSED_ENTRY => self.allocate_block(file, footer, block)?,
+                entry => u64::from(entry) * SECTOR_SIZE,
+            };
+            self.write_block(
+                file,
+                position - start,
+                block_offset,
+                start,
+                &buf[done..done + len],
+            )?;
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates `block` at the end of the file and returns its offset.
+    ///
+    /// Every step leaves a consistent file if the process dies before the next one: the footer
+    /// is first copied past the new block, so the file always ends with a footer; the bitmap is
+    /// then written over the old footer, and the BAT only points to the block once both are on
+    /// disk. An interrupted allocation leaves unused space before the footer.
+    ///
+    /// The sectors of a new block of a dynamic disk are all marked present and read as zeros
+    /// until written. Those of a differencing disk are marked absent, so they keep reading from
+    /// the parent until written.
+    fn allocate_block(&mut self, file: &File, footer: &VhdFooter, block: usize) -> io::Result<u64> {
+        let block_offset = self.footer_offset;
+        let entry = u32::try_from(block_offset / SECTOR_SIZE)
+            .ok()
+            .filter(|&entry| entry != UNUSED_ENTRY)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    "the image file is too large to allocate another block",
+                )
+            })?;
+        let new_footer_offset =
+            block_offset + self.header.bitmap_size() + u64::from(self.header.block_size);
+
+        write_all_at(file, new_footer_offset, &footer.to_bytes())?;
+        let present = if self.parent.is_some() { 0 } else { 0xff };
+        let bitmap = vec![present; self.header.bitmap_size() as usize];
+        write_all_at(file, block_offset, &bitmap)?;
+        file.sync_data()?;
+
+        let entry_offset = self.header.table_offset + block as u64 * 4;
+        write_all_at(file, entry_offset, &entry.to_be_bytes())?;
+        file.sync_data()?;
+
+        self.bat[block] = entry;
+        self.footer_offset = new_footer_offset;
+        Ok(block_offset)
+    }
+
+    /// Writes `data` at offset `start` of the block at `block_offset` in the file, which holds
+    /// the virtual disk from `block_start`, and marks the written sectors present.
+    ///
+    /// A partially written sector that was not present is completed with the content it had,
+    /// so the whole sector can be marked present. The data is on disk before the bitmap is
+    /// updated.
+    fn write_block(
+        &self,
+        file: &File,
+        block_start: u64,
+        block_offset: u64,
+        start: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut bitmap = vec![0; self.header.bitmap_size() as usize];
+        read_exact_at(file, block_offset, &mut bitmap)?;
+        let data_offset = block_offset + self.header.bitmap_size();
+
+        let end = start + data.len() as u64;
+        let first_sector = start / SECTOR_SIZE;
+        let last_sector = (end - 1) / SECTOR_SIZE;
+        let aligned_start = first_sector * SECTOR_SIZE;
+        let aligned_end = (last_sector + 1) * SECTOR_SIZE;
+
+        if aligned_start == start
+            && aligned_end == end
+            && (first_sector..=last_sector).all(|sector| bitmap_bit(&bitmap, sector))
+        {
+            return write_all_at(file, data_offset + start, data);
+        }
+
+        // complete the partially written sectors at both ends
+        let mut sectors = vec![0; (aligned_end - aligned_start) as usize];
+        let sector_len = SECTOR_SIZE as usize;
+        let last = sectors.len() - sector_len;
+        let partial = [
+            (start != aligned_start).then_some((first_sector, 0)),
+            (end != aligned_end).then_some((last_sector, last)),
+        ];
+        for (sector, at) in partial.into_iter().flatten() {
+            let buf = &mut sectors[at..at + sector_len];
+            if bitmap_bit(&bitmap, sector) {
+                read_exact_at(file, data_offset + sector * SECTOR_SIZE, buf)?;
+            } else {
+                self.read_parent(block_start + sector * SECTOR_SIZE, buf)?;
+            }
+        }
+        sectors[(start - aligned_start) as usize..(end - aligned_start) as usize]
+            .copy_from_slice(data);
+        write_all_at(file, data_offset + aligned_start, &sectors)?;
+
+        let mut changed = false;
+        for sector in first_sector..=last_sector {
+            changed |= !bitmap_bit(&bitmap, sector);
+            set_bitmap_bit(&mut bitmap, sector);
+        }
+        if changed {
+            file.sync_data()?;
+            write_all_at(file, block_offset, &bitmap)?;
+        }
+        Ok(())
+    }
+
+    /// Fills `buf` with the virtual disk of the parent at `offset`, or with zeros if there is no
+    /// parent. The part of `buf` beyond the end of the parent is zeroed.
+    fn read_parent(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let read = match &self.parent {
+            Some(parent) => parent.read_at(offset, buf)?,
+            None => 0,
+        };
+        buf[read..].fill(0);
+        Ok(())
+    }
+}
+
+/// Opens the parent of the differencing disk at `path`, whose file and dynamic disk header are
+/// `file` and `header`. See [`VhdImage::open`].
+fn open_parent(path: &Path, file: &File, header: &DynamicHeader, depth: usize) -> Result<VhdImage> {
+    if depth + 1 >= MAX_CHAIN_DEPTH {
+        return Err(Error::new(ErrorKind::Corrupt, "open").with_source(format!(
+            "differencing chain is longer than {MAX_CHAIN_DEPTH} disks"
+        )));
+    }
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut candidates = Vec::new();
+    for platform_code in LOCATOR_ORDER {
+        for locator in header
+            .parent_locators
+            .iter()
+            .filter(|locator| locator.platform_code == platform_code)
+        {
+            let mut data = vec![0; locator.data_length as usize];
+            read_exact_at(file, locator.data_offset, &mut data)
+                .map_err(|e| Error::io("open", e))?;
+            if let Some(parent_path) = locator.decode_path(&data) {
+                candidates.push(directory.join(native_path(&parent_path)));
+            }
+        }
+    }
+    if let Some(name) = native_path(&header.parent_name).file_name() {
+        candidates.push(directory.join(name));
+    }
+
+    let mut mismatch = None;
+    for candidate in candidates {
+        if !candidate.is_file() {
+            continue;
+        }
+
+        let parent =
+            VhdImage::open_file(&candidate, OpenMode::ReadOnly, depth + 1).map_err(|e| {
+                let e = e.with_path(&candidate);
+                Error::new(e.kind(), "open").with_source(e)
+            })?;
+        if parent.footer.unique_id == header.parent_unique_id {
+            return Ok(parent);
+        }
+        mismatch.get_or_insert((candidate, parent.footer.unique_id));
+    }
+
+    match mismatch {
+        Some((candidate, unique_id)) => {
+            Err(Error::new(ErrorKind::Corrupt, "open").with_source(format!(
+                "parent `{}` has unique ID {unique_id}, expected {}",
+                candidate.display(),
+                header.parent_unique_id
+            )))
+        }
+        None => Err(Error::new(ErrorKind::NotFound, "open").with_source(format!(
+            "parent `{}` of differencing disk not found",
+            header.parent_name
+        ))),
+    }
+}
+
+/// Converts a path stored in a parent locator to a path of the current platform.
+fn native_path(path: &str) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(path)
+    } else {
+        PathBuf::from(path.replace('\\', "/"))
+    }
+}
+
+/// Failure while reading a structure: either the I/O or the parsing failed.
+#[derive(Debug)]
+enum ReadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+type ReadResult<T> = std::result::Result<T, ReadError>;
+
+impl ReadError {
+    fn into_error(self, operation: &'static str) -> Error {
+        match self {
+            ReadError::Io(error) => Error::io(operation, error),
+            ReadError::Parse(error) => Error::corrupt(operation, error),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> Self {
+        ReadError::Io(error)
+    }
+}
+
+impl From<ParseError> for ReadError {
+    fn from(error: ParseError) -> Self {
+        ReadError::Parse(error)
+    }
+}
+
+impl Read for VhdImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// Writes to the virtual disk. Fails for images opened read-only.
+impl Write for VhdImage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.write_at(self.position, buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+impl Seek for VhdImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(self.position, self.virtual_size(), pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Resolves `pos` against the current `position` and the `size` of a stream. Seeking past the
+/// end is allowed, seeking before the start is not.
+pub(crate) fn seek_position(position: u64, size: u64, pos: SeekFrom) -> io::Result<u64> {
+    let (base, delta) = match pos {
+        SeekFrom::Start(offset) => return Ok(offset),
+        SeekFrom::End(delta) => (size, delta),
+        SeekFrom::Current(delta) => (position, delta),
+    };
+
+    base.checked_add_signed(delta).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+/// Reads exactly `buf.len()` bytes from `file` at `offset`.
+pub(crate) fn read_exact_at(mut file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+/// Writes all of `buf` to `file` at `offset`.
+pub(crate) fn write_all_at(mut file: &File, offset: u64, buf: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        differencing_vhd, dynamic_structures, dynamic_vhd, dynamic_vhd_bytes, fixed_footer,
+        fixed_vhd, Block, TempFile,
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn read_fixed() {
+        let data: Vec<u8> = (0..4096u32).map(|i| i as u8).collect();
+        let file = fixed_vhd(&data);
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert_eq!(image.virtual_size(), 4096);
+
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, data);
+
+        image.seek(SeekFrom::Start(1000)).unwrap();
+        let mut buf = [0; 8];
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[1000..1008]);
+
+        // reads stop at the end of the virtual disk, before the footer
+        image.seek(SeekFrom::End(-4)).unwrap();
+        assert_eq!(image.read(&mut buf).unwrap(), 4);
+        assert_eq!(image.read(&mut buf).unwrap(), 0);
+        assert!(image.seek(SeekFrom::Current(-5000)).is_err());
+    }
+
+    #[test]
+    fn write_fixed() {
+        let file = fixed_vhd(&[0; 2048]);
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+
+        image.seek(SeekFrom::Start(1000)).unwrap();
+        image.write_all(&[0xaa; 24]).unwrap();
+        // writes stop at the end of the virtual disk and leave the footer intact
+        image.seek(SeekFrom::End(-2)).unwrap();
+        assert_eq!(image.write(&[0xbb; 4]).unwrap(), 2);
+        image.flush().unwrap();
+
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents[1000..1024], [0xaa; 24]);
+        assert_eq!(contents[2046..], [0xbb; 2]);
+
+        let error = image.write(&[0]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn current_size_beyond_file() {
+        let mut bytes = vec![0; 1024];
+        bytes.extend_from_slice(&fixed_footer(2048).to_bytes());
+        let file = TempFile::with_contents("too-small.vhd", &bytes);
+
+        let error = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.field(), "current size");
+        assert_eq!(parse_error.offset(), 1024 + 48);
+    }
+
+    #[test]
+    fn missing_footer() {
+        let file = TempFile::with_contents("no-footer.vhd", &[0; 1024]);
+        let error = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        assert_eq!(error.path(), Some(file.path()));
+        assert_eq!(error.parse_error().unwrap().field(), "cookie");
+        assert_eq!(error.parse_error().unwrap().offset(), 512);
+    }
+
+    #[test]
+    fn read_dynamic() {
+        let block_size = 4096;
+        let first: Vec<u8> = (0..block_size).map(|i| i as u8).collect();
+        let third = vec![0xab; block_size as usize];
+        let file = dynamic_vhd(
+            4 * u64::from(block_size),
+            block_size,
+            &[Block::full(0, first.clone()), Block::full(2, third.clone())],
+        );
+
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert_eq!(image.virtual_size(), 16384);
+        assert_eq!(image.block_size(), block_size);
+        assert_eq!(image.bat().unwrap()[1], UNUSED_ENTRY);
+
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        let mut expected = first;
+        expected.resize(8192, 0);
+        expected.extend_from_slice(&third);
+        expected.resize(16384, 0);
+        assert_eq!(contents, expected);
+
+        // a read spanning an unallocated and an allocated block
+        image.seek(SeekFrom::Start(8190)).unwrap();
+        let mut buf = [1; 4];
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0, 0, 0xab, 0xab]);
+    }
+
+    #[test]
+    fn read_dynamic_honours_bitmap() {
+        // only the second and fourth sectors of the block are present
+        let block = Block {
+            index: 0,
+            bitmap: vec![0b0101_0000],
+            data: vec![0xcd; 4096],
+        };
+        let file = dynamic_vhd(4096, 4096, &[block]);
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert!(contents[..512].iter().all(|&b| b == 0));
+        assert!(contents[512..1024].iter().all(|&b| b == 0xcd));
+        assert!(contents[1024..1536].iter().all(|&b| b == 0));
+        assert!(contents[1536..2048].iter().all(|&b| b == 0xcd));
+        assert!(contents[2048..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn bat_entry_beyond_footer() {
+        let (footer, header) = dynamic_structures(8192, 4096);
+        let mut bytes = dynamic_vhd_bytes(&footer, &header, &[]);
+        bytes[1540..1544].copy_from_slice(&100u32.to_be_bytes());
+        let file = TempFile::with_contents("bad-bat.vhd", &bytes);
+
+        let error = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.structure(), Structure::BatEntry);
+        assert_eq!(parse_error.offset(), 1540);
+        assert_eq!(parse_error.found(), Some("100"));
+    }
+
+    #[test]
+    fn too_few_table_entries() {
+        let (footer, mut header) = dynamic_structures(8192, 4096);
+        header.max_table_entries = 1;
+        let bytes = dynamic_vhd_bytes(&footer, &header, &[]);
+        let file = TempFile::with_contents("small-bat.vhd", &bytes);
+
+        let error = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap_err();
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.field(), "max table entries");
+        assert_eq!(parse_error.offset(), 512 + 28);
+    }
+
+    #[test]
+    fn read_differencing() {
+        let parent = dynamic_vhd(
+            8192,
+            4096,
+            &[
+                Block::full(0, vec![0x11; 4096]),
+                Block::full(1, vec![0x22; 4096]),
+            ],
+        );
+        // only the second sector of the first block is written in the child
+        let block = Block {
+            index: 0,
+            bitmap: vec![0b0100_0000],
+            data: vec![0xcc; 4096],
+        };
+        let child = differencing_vhd(8192, 4096, parent.path(), Uuid::from_u128(1), &[block]);
+
+        let mut image = VhdImage::open(child.path(), OpenMode::ReadOnly).unwrap();
+        assert_eq!(image.footer().disk_type, DiskType::Differencing);
+        assert_eq!(image.parent().unwrap().path(), parent.path());
+
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert!(contents[..512].iter().all(|&b| b == 0x11));
+        assert!(contents[512..1024].iter().all(|&b| b == 0xcc));
+        assert!(contents[1024..4096].iter().all(|&b| b == 0x11));
+        assert!(contents[4096..].iter().all(|&b| b == 0x22));
+    }
+
+    #[test]
+    fn parent_unique_id_mismatch() {
+        let parent = dynamic_vhd(4096, 4096, &[]);
+        let child = differencing_vhd(4096, 4096, parent.path(), Uuid::from_u128(7), &[]);
+
+        let error = VhdImage::open(child.path(), OpenMode::ReadOnly).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        assert_eq!(error.path(), Some(child.path()));
+        assert!(error.to_string().contains("unique ID"), "{error}");
+    }
+
+    #[test]
+    fn parent_not_found() {
+        let parent = TempFile::new("missing-parent.vhd");
+        let child = differencing_vhd(4096, 4096, parent.path(), Uuid::from_u128(1), &[]);
+
+        let error = VhdImage::open(child.path(), OpenMode::ReadOnly).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert!(error.to_string().contains("missing-parent.vhd"), "{error}");
+    }
+
+    #[test]
+    fn write_dynamic() {
+        let file = dynamic_vhd(16384, 4096, &[]);
+        let physical_size = std::fs::metadata(file.path()).unwrap().len();
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+
+        // a write spanning the first two blocks allocates both
+        image.seek(SeekFrom::Start(4000)).unwrap();
+        image.write_all(&[0x5a; 200]).unwrap();
+        image.flush().unwrap();
+        assert_eq!(
+            image.physical_size().unwrap(),
+            physical_size + 2 * (512 + 4096)
+        );
+
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        let bat = image.bat().unwrap();
+        assert_ne!(bat[0], UNUSED_ENTRY);
+        assert_ne!(bat[1], UNUSED_ENTRY);
+        assert_eq!(bat[2], UNUSED_ENTRY);
+
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        let mut expected = vec![0; 16384];
+        expected[4000..4200].fill(0x5a);
+        assert_eq!(contents, expected);
+
+        // the footer copy at offset 0 still matches the relocated footer
+        let bytes = std::fs::read(file.path()).unwrap();
+        assert_eq!(bytes[..512], bytes[bytes.len() - 512..]);
+    }
+
+    #[test]
+    fn write_dynamic_partial_sector() {
+        // the second and third sectors are present, the first is not
+        let block = Block {
+            index: 0,
+            bitmap: vec![0b0110_0000],
+            data: vec![0xcd; 4096],
+        };
+        let file = dynamic_vhd(4096, 4096, &[block]);
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+
+        image.seek(SeekFrom::Start(100)).unwrap();
+        image.write_all(&[0x5a; 10]).unwrap();
+        image.seek(SeekFrom::Start(1100)).unwrap();
+        image.write_all(&[0x5b; 10]).unwrap();
+
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        let mut expected = vec![0; 4096];
+        expected[100..110].fill(0x5a);
+        expected[512..1536].fill(0xcd);
+        expected[1100..1110].fill(0x5b);
+        assert_eq!(contents, expected);
+    }
+
+    #[test]
+    fn interrupted_allocation() {
+        // a block was written past the footer but the BAT was not updated
+        let (footer, header) = dynamic_structures(8192, 4096);
+        let mut bytes = dynamic_vhd_bytes(&footer, &header, &[]);
+        bytes.truncate(bytes.len() - 512);
+        bytes.extend_from_slice(&[0xee; 512 + 4096]);
+        bytes.extend_from_slice(&footer.to_bytes());
+        let file = TempFile::with_contents("interrupted.vhd", &bytes);
+
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, vec![0; 8192]);
+
+        image.seek(SeekFrom::Start(4096)).unwrap();
+        image.write_all(&[0x5a; 512]).unwrap();
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents[..4096], [0; 4096]);
+        assert_eq!(contents[4096..4608], [0x5a; 512]);
+        assert_eq!(contents[4608..], [0; 3584]);
+    }
+
+    #[test]
+    fn write_differencing() {
+        let parent_data: Vec<u8> = (0..16384u32).map(|i| (i % 251) as u8).collect();
+        let parent = fixed_vhd(&parent_data);
+        let child = differencing_vhd(16384, 4096, parent.path(), Uuid::from_u128(1), &[]);
+        let mut image = VhdImage::open(child.path(), OpenMode::ReadWrite).unwrap();
+
+        // partial sectors at both ends of a write spanning the first two blocks
+        image.seek(SeekFrom::Start(4000)).unwrap();
+        image.write_all(&[0x5a; 700]).unwrap();
+        // a sector-aligned write in the last block
+        image.seek(SeekFrom::Start(12800)).unwrap();
+        image.write_all(&[0x5b; 512]).unwrap();
+
+        let mut image = VhdImage::open(child.path(), OpenMode::ReadOnly).unwrap();
+        let mut expected = parent_data.clone();
+        expected[4000..4700].fill(0x5a);
+        expected[12800..13312].fill(0x5b);
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, expected);
+
+        // only the written sectors are present in the child
+        let bitmap = |block: usize| {
+            let entry = image.bat().unwrap()[block];
+            let mut bitmap = [0; 1];
+            read_exact_at(&image.file, u64::from(entry) * 512, &mut bitmap).unwrap();
+            bitmap[0]
+        };
+        assert_eq!(bitmap(0), 0b0000_0001);
+        assert_eq!(bitmap(1), 0b1100_0000);
+        assert_eq!(image.bat().unwrap()[2], UNUSED_ENTRY);
+        assert_eq!(bitmap(3), 0b0100_0000);
+
+        // the parent is untouched
+        let mut parent = VhdImage::open(parent.path(), OpenMode::ReadOnly).unwrap();
+        let mut contents = Vec::new();
+        parent.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, parent_data);
+    }
+}
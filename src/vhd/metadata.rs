@@ -0,0 +1,154 @@
+use std::fmt::{self, Display};
+use std::time::{Duration, SystemTime};
+
+use super::{DiskType, VhdFooter};
+
+/// Seconds between the Unix epoch and the VHD epoch, January 1, 2000 12:00:00 AM UTC.
+const VHD_EPOCH: u64 = 946_684_800;
+
+/// A version stored in a footer field, major version in the high 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl Version {
+    fn from_u32(value: u32) -> Self {
+        Version {
+            major: (value >> 16) as u16,
+            minor: value as u16,
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The host OS an image was created on, as stored in the footer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostOs {
+    /// `Wi2k`, written by Windows and by most other tools.
+    Windows,
+    /// `Mac `, written by Virtual PC for Mac.
+    Macintosh,
+    /// Any other code.
+    Other(u32),
+}
+
+impl HostOs {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            0x5769_326b => HostOs::Windows,
+            0x4d61_6320 => HostOs::Macintosh,
+            other => HostOs::Other(other),
+        }
+    }
+}
+
+impl Display for HostOs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostOs::Windows => f.write_str("Windows"),
+            HostOs::Macintosh => f.write_str("Macintosh"),
+            HostOs::Other(code) => write!(f, "{code:#010x}"),
+        }
+    }
+}
+
+/// Descriptive metadata of a VHD, decoded from its footer: which application created it, on
+/// which host, and when it was last written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdMetadata {
+    /// Code of the application that created the image without its padding, e.g. `vpc` for
+    /// Virtual PC, `win` for Windows or `qemu` for QEMU.
+    pub creator_application: String,
+    pub creator_version: Version,
+    pub creator_host_os: HostOs,
+    /// Version of the VHD format, 1.0 for every image that follows the specification.
+    pub format_version: Version,
+    /// When the image was created or last written, to the second.
+    pub timestamp: SystemTime,
+    /// Whether the disk is in a saved state (the VM was hibernated).
+    pub saved_state: bool,
+    pub disk_type: DiskType,
+}
+
+impl VhdFooter {
+    /// Decodes the descriptive metadata of the footer.
+    pub fn metadata(&self) -> VhdMetadata {
+        let creator_application = String::from_utf8_lossy(&self.creator_application)
+            .trim_end_matches([' ', '\0'])
+            .to_owned();
+
+        VhdMetadata {
+            creator_application,
+            creator_version: Version::from_u32(self.creator_version),
+            creator_host_os: HostOs::from_u32(self.creator_host_os),
+            format_version: Version::from_u32(self.format_version),
+            timestamp: to_system_time(self.timestamp),
+            saved_state: self.saved_state,
+            disk_type: self.disk_type,
+        }
+    }
+}
+
+/// Converts a VHD timestamp, in seconds since the VHD epoch, to a [`SystemTime`].
+pub(crate) fn to_system_time(timestamp: u32) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(VHD_EPOCH + u64::from(timestamp))
+}
+
+/// Converts `time` to a VHD timestamp, saturating at the VHD epoch.
+pub(crate) fn from_system_time(time: SystemTime) -> u32 {
+    let unix = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    unix.saturating_sub(VHD_EPOCH) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::fixed_footer;
+
+    #[test]
+    fn metadata() {
+        let mut footer = fixed_footer(4096);
+        footer.creator_application = *b"vpc ";
+        footer.creator_version = 0x0005_0003;
+        footer.timestamp = 86_400;
+        footer.saved_state = true;
+
+        let metadata = footer.metadata();
+        assert_eq!(metadata.creator_application, "vpc");
+        assert_eq!(metadata.creator_version.to_string(), "5.3");
+        assert_eq!(metadata.creator_host_os, HostOs::Windows);
+        assert_eq!(metadata.format_version, Version { major: 1, minor: 0 });
+        assert_eq!(
+            metadata.timestamp,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(946_771_200)
+        );
+        assert!(metadata.saved_state);
+        assert_eq!(metadata.disk_type, DiskType::Fixed);
+    }
+
+    #[test]
+    fn host_os() {
+        assert_eq!(
+            HostOs::from_u32(u32::from_be_bytes(*b"Mac ")),
+            HostOs::Macintosh
+        );
+        let other = HostOs::from_u32(u32::from_be_bytes(*b"Lnx "));
+        assert_eq!(other.to_string(), "0x4c6e7820");
+    }
+
+    #[test]
+    fn timestamps() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(to_system_time(from_system_time(time)), time);
+        assert_eq!(from_system_time(SystemTime::UNIX_EPOCH), 0);
+    }
+}
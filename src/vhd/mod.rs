@@ -0,0 +1,45 @@
+//! Pure-Rust implementation of the VHD file format (Virtual Hard Disk Image Format
+//! Specification, version 1.0).
+
+pub use create::{Allocation, CreateOptions};
+pub use dynamic::{DynamicHeader, ParentLocator, PlatformCode};
+pub use footer::{DiskGeometry, DiskType, VhdFooter};
+pub use image::VhdImage;
+pub use metadata::{HostOs, Version, VhdMetadata};
+pub use repair::FooterRepair;
+
+pub(crate) use image::{read_exact_at, seek_position, write_all_at};
+
+mod create;
+mod dynamic;
+mod footer;
+mod image;
+mod metadata;
+mod repair;
+
+/// Reads a big-endian `u16` at `offset` of `bytes`.
+fn be_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+/// Reads a big-endian `u32` at `offset` of `bytes`.
+fn be_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a big-endian `u64` at `offset` of `bytes`.
+fn be_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Computes the one's complement checksum used by the footer and the dynamic disk header: the
+/// complement of the sum of all bytes, with the 4-byte checksum field at `checksum_offset`
+/// treated as zero.
+fn checksum(bytes: &[u8], checksum_offset: usize) -> u32 {
+    let sum = bytes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !(checksum_offset..checksum_offset + 4).contains(i))
+        .fold(0u32, |sum, (_, &byte)| sum.wrapping_add(u32::from(byte)));
+    !sum
+}
@@ -0,0 +1,283 @@
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+use super::dynamic::{parse_bat, HEADER_SIZE, SECTOR_SIZE, UNUSED_ENTRY};
+use super::footer::FOOTER_SIZE;
+use super::image::{read_exact_at, write_all_at};
+use super::{DiskType, DynamicHeader, PlatformCode, VhdFooter, VhdImage};
+use crate::{Error, ErrorKind, ParseError, Result, Structure};
+
+/// What [`VhdImage::repair_footer`] changed to restore the trailing footer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FooterRepair {
+    /// Why the trailing footer was rejected.
+    pub problem: ParseError,
+    /// Offset the footer copy was written to: the end of the data of the image.
+    pub footer_offset: u64,
+    /// Size of the file before the repair.
+    pub old_file_size: u64,
+    /// Size of the file after the repair, `footer_offset` + 512.
+    pub new_file_size: u64,
+}
+
+impl Display for FooterRepair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rewrote the footer at offset {:#x} from the copy at offset 0 ({}); file size \
+             changed from {} to {} bytes",
+            self.footer_offset, self.problem, self.old_file_size, self.new_file_size
+        )
+    }
+}
+
+impl VhdImage {
+    /// Checks the trailing footer of the dynamic or differencing VHD at `path` and, if it is
+    /// missing or invalid, restores it from the footer copy at offset 0.
+    ///
+    /// The footer is written right after the last structure the dynamic disk header, the BAT
+    /// and the parent locators reference, and the file is truncated or extended to end with it.
+    /// Returns `None` if the trailing footer is valid and nothing was changed.
+    ///
+    /// # Errors
+    /// An [`ErrorKind::Corrupt`] error if the footer copy or the dynamic disk header is invalid
+    /// too, or is the copy of a fixed disk, or an error if the file cannot be read or written.
+    pub fn repair_footer<P: AsRef<Path>>(path: P) -> Result<Option<FooterRepair>> {
+        let path = path.as_ref();
+        repair_footer(path).map_err(|e| e.with_path(path))
+    }
+}
+
+fn repair_footer(path: &Path) -> Result<Option<FooterRepair>> {
+    let io_error = |e| Error::io("repair", e);
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(io_error)?;
+    let old_file_size = file.metadata().map_err(io_error)?.len();
+
+    let problem = match old_file_size.checked_sub(FOOTER_SIZE as u64) {
+        Some(footer_offset) => {
+            let mut bytes = [0; FOOTER_SIZE];
+            read_exact_at(&file, footer_offset, &mut bytes).map_err(io_error)?;
+            match VhdFooter::parse(&bytes, footer_offset) {
+                Ok(_) => return Ok(None),
+                Err(problem) => problem,
+            }
+        }
+        None => ParseError::mismatch(
+            Structure::Footer,
+            "file size",
+            0,
+            "at least 512 bytes",
+            old_file_size,
+        ),
+    };
+
+    let mut copy = [0; FOOTER_SIZE];
+    read_exact_at(&file, 0, &mut copy).map_err(io_error)?;
+    let footer = VhdFooter::parse(&copy, 0).map_err(|e| Error::corrupt("repair", e))?;
+    if !matches!(footer.disk_type, DiskType::Dynamic | DiskType::Differencing) {
+        return Err(
+            Error::new(ErrorKind::Corrupt, "repair").with_source(format!(
+                "the footer copy at offset 0 is the one of a {} disk",
+                footer.disk_type
+            )),
+        );
+    }
+
+    let footer_offset = end_of_data(&file, &footer, old_file_size)?;
+    write_all_at(&file, footer_offset, &copy).map_err(io_error)?;
+    let new_file_size = footer_offset + FOOTER_SIZE as u64;
+    file.set_len(new_file_size).map_err(io_error)?;
+    file.sync_all().map_err(io_error)?;
+
+    Ok(Some(FooterRepair {
+        problem,
+        footer_offset,
+        old_file_size,
+        new_file_size,
+    }))
+}
+
+/// Returns the end of the last structure of the dynamic or differencing disk whose footer is
+/// `footer`, rounded up to a sector. The last block may extend past the end of the file, of
+/// `file_size` bytes, if the file was truncated, but every block must start within it.
+fn end_of_data(file: &File, footer: &VhdFooter, file_size: u64) -> Result<u64> {
+    let io_error = |e| Error::io("repair", e);
+
+    let header_offset = footer.data_offset;
+    let mut bytes = [0; HEADER_SIZE];
+    read_exact_at(file, header_offset, &mut bytes).map_err(io_error)?;
+    let header =
+        DynamicHeader::parse(&bytes, header_offset).map_err(|e| Error::corrupt("repair", e))?;
+
+    // bound the BAT by the file before allocating it, as its size comes from the header
+    let table_end = header.table_offset.saturating_add(header.table_size());
+    if table_end > file_size {
+        return Err(Error::corrupt(
+            "repair",
+            ParseError::mismatch(
+                Structure::DynamicHeader,
+                "table offset",
+                header_offset + 16,
+                format!("at most {}", file_size.saturating_sub(header.table_size())),
+                header.table_offset,
+            ),
+        ));
+    }
+
+    let mut end = header_offset + HEADER_SIZE as u64;
+    end = end.max(table_end);
+
+    for locator in &header.parent_locators {
+        if locator.platform_code != PlatformCode::None {
+            end = end.max(
+                locator
+                    .data_offset
+                    .saturating_add(u64::from(locator.data_length)),
+            );
+        }
+    }
+
+    let mut bytes = vec![0; header.max_table_entries as usize * 4];
+    read_exact_at(file, header.table_offset, &mut bytes).map_err(io_error)?;
+    let block_span = header.bitmap_size() + u64::from(header.block_size);
+    for (index, entry) in parse_bat(&bytes).into_iter().enumerate() {
+        if entry == UNUSED_ENTRY {
+            continue;
+        }
+
+        let block_offset = u64::from(entry) * SECTOR_SIZE;
+        if block_offset >= file_size {
+            return Err(Error::corrupt(
+                "repair",
+                ParseError::mismatch(
+                    Structure::BatEntry,
+                    "block sector offset",
+                    header.table_offset + index as u64 * 4,
+                    format!("less than {}", file_size.div_ceil(SECTOR_SIZE)),
+                    entry,
+                ),
+            ));
+        }
+        end = end.max(block_offset + block_span);
+    }
+
+    Ok(end.next_multiple_of(SECTOR_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use super::*;
+    use crate::test_util::{fixed_vhd, TempFile};
+    use crate::{CreateOptions, OpenMode};
+
+    /// Creates a dynamic VHD with one allocated block and returns it with its size.
+    fn dynamic_with_data() -> (TempFile, u64) {
+        let file = TempFile::new("repair.vhd");
+        let options = CreateOptions::new().block_size(512 << 10);
+        let mut image = VhdImage::create_dynamic(file.path(), 4 << 20, &options).unwrap();
+        image.seek(SeekFrom::Start(1 << 20)).unwrap();
+        image.write_all(b"data").unwrap();
+        let size = image.physical_size().unwrap();
+        (file, size)
+    }
+
+    fn set_len(file: &TempFile, len: u64) {
+        File::options()
+            .write(true)
+            .open(file.path())
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+    }
+
+    fn read_back(file: &TempFile) -> Vec<u8> {
+        let mut image = VhdImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        let mut buf = vec![0; 4];
+        image.seek(SeekFrom::Start(1 << 20)).unwrap();
+        image.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn healthy_footer() {
+        let (file, size) = dynamic_with_data();
+        assert_eq!(VhdImage::repair_footer(file.path()).unwrap(), None);
+        assert_eq!(std::fs::metadata(file.path()).unwrap().len(), size);
+    }
+
+    #[test]
+    fn truncated_footer() {
+        let (file, size) = dynamic_with_data();
+        set_len(&file, size - 300);
+
+        let repair = VhdImage::repair_footer(file.path()).unwrap().unwrap();
+        assert_eq!(repair.problem.structure(), Structure::Footer);
+        assert_eq!(repair.footer_offset, size - 512);
+        assert_eq!(repair.old_file_size, size - 300);
+        assert_eq!(repair.new_file_size, size);
+        assert_eq!(read_back(&file), b"data");
+    }
+
+    #[test]
+    fn missing_footer_with_trailing_garbage() {
+        let (file, size) = dynamic_with_data();
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        bytes.truncate(bytes.len() - 512);
+        bytes.extend_from_slice(&[0xee; 1000]);
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let repair = VhdImage::repair_footer(file.path()).unwrap().unwrap();
+        assert_eq!(repair.problem.field(), "cookie");
+        assert_eq!(repair.new_file_size, size);
+        assert!(repair.to_string().contains("from the copy at offset 0"));
+        assert_eq!(read_back(&file), b"data");
+    }
+
+    #[test]
+    fn bad_copy() {
+        let (file, size) = dynamic_with_data();
+        set_len(&file, size - 512);
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        bytes[100] ^= 1;
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let error = VhdImage::repair_footer(file.path()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        assert_eq!(error.parse_error().unwrap().field(), "checksum");
+        assert_eq!(error.path(), Some(file.path()));
+    }
+
+    #[test]
+    fn table_beyond_end_of_file() {
+        let (file, size) = dynamic_with_data();
+        set_len(&file, size - 512);
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        let header_bytes: &[u8; HEADER_SIZE] = bytes[512..512 + HEADER_SIZE].try_into().unwrap();
+        let mut header = DynamicHeader::parse(header_bytes, 512).unwrap();
+        header.max_table_entries = u32::MAX;
+        bytes[512..512 + HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let error = VhdImage::repair_footer(file.path()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.structure(), Structure::DynamicHeader);
+        assert_eq!(parse_error.field(), "table offset");
+    }
+
+    #[test]
+    fn fixed_disk() {
+        let file = fixed_vhd(&[0; 1024]);
+        set_len(&file, 1024);
+        let error = VhdImage::repair_footer(file.path()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+    }
+}
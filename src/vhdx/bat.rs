@@ -0,0 +1,457 @@
+use std::ops::Range;
+
+use super::header::MIB;
+use super::VhdxMetadata;
+use crate::{ParseError, Structure};
+
+/// Size of a BAT entry.
+pub(crate) const ENTRY_SIZE: usize = 8;
+/// Size of a sector bitmap block.
+pub(crate) const SECTOR_BITMAP_BLOCK_SIZE: u64 = MIB;
+/// Number of sectors a sector bitmap block describes, one bit each.
+const SECTORS_PER_CHUNK: u64 = SECTOR_BITMAP_BLOCK_SIZE * 8;
+
+const STATE_MASK: u64 = 0b111;
+const FILE_OFFSET_SHIFT: u32 = 20;
+const SECTOR_BITMAP_PRESENT: u64 = 6;
+
+/// The state of a payload block, stored in bits 0 to 2 of its BAT entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PayloadState {
+    /// The block is not allocated; it reads as zeros, or from the parent of a differencing
+    /// disk.
+    NotPresent,
+    /// The contents of the block are undefined and may be anything.
+    Undefined,
+    /// The block reads as zeros.
+    Zero,
+    /// The block was unmapped (trimmed); it reads as zeros or as its former contents.
+    Unmapped,
+    /// The block is allocated and all its sectors are stored in the file.
+    FullyPresent,
+    /// The block is allocated in a differencing disk and its sector bitmap tells which sectors
+    /// are stored in the file rather than in the parent.
+    PartiallyPresent,
+}
+
+impl PayloadState {
+    fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(PayloadState::NotPresent),
+            1 => Some(PayloadState::Undefined),
+            2 => Some(PayloadState::Zero),
+            3 => Some(PayloadState::Unmapped),
+            6 => Some(PayloadState::FullyPresent),
+            7 => Some(PayloadState::PartiallyPresent),
+            _ => None,
+        }
+    }
+
+    fn to_bits(self) -> u64 {
+        match self {
+            PayloadState::NotPresent => 0,
+            PayloadState::Undefined => 1,
+            PayloadState::Zero => 2,
+            PayloadState::Unmapped => 3,
+            PayloadState::FullyPresent => 6,
+            PayloadState::PartiallyPresent => 7,
+        }
+    }
+
+    /// Returns whether the block has space in the file.
+    pub fn is_allocated(self) -> bool {
+        matches!(
+            self,
+            PayloadState::FullyPresent | PayloadState::PartiallyPresent
+        )
+    }
+}
+
+/// The BAT entry of a payload block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PayloadBlock {
+    pub state: PayloadState,
+    /// Absolute offset of the block in the file, a multiple of 1 MiB, or 0 if it has none.
+    pub file_offset: u64,
+}
+
+impl PayloadBlock {
+    /// Serializes the BAT entry.
+    pub fn to_u64(self) -> u64 {
+        self.state.to_bits() | self.file_offset
+    }
+}
+
+/// The BAT entry of a sector bitmap block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SectorBitmapBlock {
+    /// Whether the block is allocated in the file.
+    pub present: bool,
+    /// Absolute offset of the block in the file, a multiple of 1 MiB, or 0 if it has none.
+    pub file_offset: u64,
+}
+
+impl SectorBitmapBlock {
+    /// Serializes the BAT entry.
+    pub fn to_u64(self) -> u64 {
+        if self.present {
+            SECTOR_BITMAP_PRESENT | self.file_offset
+        } else {
+            self.file_offset
+        }
+    }
+}
+
+/// The Block Allocation Table of a VHDX file.
+///
+/// The BAT interleaves the entries of the payload blocks with those of the sector bitmap
+/// blocks: every [`VhdxBat::chunk_ratio`] payload entries are followed by the entry of the sector
+/// bitmap block that describes their sectors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdxBat {
+    chunk_ratio: u64,
+    block_size: u32,
+    logical_sector_size: u32,
+    virtual_disk_size: u64,
+    payload_blocks: Vec<PayloadBlock>,
+    sector_bitmap_blocks: Vec<SectorBitmapBlock>,
+}
+
+impl VhdxBat {
+    /// Returns the number of entries of the BAT of a file with `metadata`.
+    pub(crate) fn entry_count(metadata: &VhdxMetadata) -> u64 {
+        let chunk_ratio = chunk_ratio(metadata);
+        let payload_blocks = payload_block_count(metadata);
+        if metadata.file_parameters.has_parent {
+            payload_blocks.div_ceil(chunk_ratio) * (chunk_ratio + 1)
+        } else {
+            payload_blocks + payload_blocks.saturating_sub(1) / chunk_ratio
+        }
+    }
+
+    /// Parses the BAT of a file with `metadata` from `bytes`, which were read from file offset
+    /// `offset` and hold at least [`VhdxBat::entry_count`] entries.
+    ///
+    /// # Errors
+    /// If an entry has an unknown state, a state that is only valid in differencing disks, or
+    /// no file offset although it is allocated.
+    pub(crate) fn parse(
+        bytes: &[u8],
+        offset: u64,
+        metadata: &VhdxMetadata,
+    ) -> Result<Self, ParseError> {
+        let chunk_ratio = chunk_ratio(metadata);
+        let has_parent = metadata.file_parameters.has_parent;
+        let entry = |index: u64| {
+            let start = index as usize * ENTRY_SIZE;
+            let value = u64::from_le_bytes(bytes[start..start + ENTRY_SIZE].try_into().unwrap());
+            (
+                value & STATE_MASK,
+                value >> FILE_OFFSET_SHIFT << FILE_OFFSET_SHIFT,
+                offset + start as u64,
+            )
+        };
+
+        let payload_count = payload_block_count(metadata);
+        let mut payload_blocks = Vec::with_capacity(payload_count as usize);
+        for block in 0..payload_count {
+            let (bits, file_offset, entry_offset) = entry(block + block / chunk_ratio);
+            let state = match PayloadState::from_bits(bits) {
+                Some(PayloadState::PartiallyPresent) if !has_parent => None,
+                state => state,
+            };
+            let state = state.ok_or_else(|| {
+                ParseError::new(Structure::VhdxBatEntry, "state", entry_offset)
+                    .with_expected(if has_parent {
+                        "0, 1, 2, 3, 6 or 7"
+                    } else {
+                        "0, 1, 2, 3 or 6"
+                    })
+                    .with_found(bits)
+            })?;
+            if state.is_allocated() && file_offset == 0 {
+                return Err(ParseError::mismatch(
+                    Structure::VhdxBatEntry,
+                    "file offset",
+                    entry_offset,
+                    "a non-zero offset",
+                    0,
+                ));
+            }
+            payload_blocks.push(PayloadBlock { state, file_offset });
+        }
+
+        let sector_bitmap_count = if has_parent {
+            payload_count.div_ceil(chunk_ratio)
+        } else {
+            0
+        };
+        let mut sector_bitmap_blocks = Vec::with_capacity(sector_bitmap_count as usize);
+        for chunk in 0..sector_bitmap_count {
+            let (bits, file_offset, entry_offset) = entry(chunk * (chunk_ratio + 1) + chunk_ratio);
+            let present = match bits {
+                0 => false,
+                SECTOR_BITMAP_PRESENT if file_offset != 0 => true,
+                _ => {
+                    return Err(
+                        ParseError::new(Structure::VhdxBatEntry, "state", entry_offset)
+                            .with_expected("0, or 6 with a non-zero file offset")
+                            .with_found(bits),
+                    )
+                }
+            };
+
+            let first_block = (chunk * chunk_ratio) as usize;
+            let blocks = &payload_blocks
+                [first_block..payload_blocks.len().min(first_block + chunk_ratio as usize)];
+            if !present
+                && blocks
+                    .iter()
+                    .any(|block| block.state == PayloadState::PartiallyPresent)
+            {
+                return Err(ParseError::mismatch(
+                    Structure::VhdxBatEntry,
+                    "state",
+                    entry_offset,
+                    format!("{SECTOR_BITMAP_PRESENT} for a chunk with partially present blocks"),
+                    bits,
+                ));
+            }
+            sector_bitmap_blocks.push(SectorBitmapBlock {
+                present,
+                file_offset,
+            });
+        }
+
+        Ok(VhdxBat {
+            chunk_ratio,
+            block_size: metadata.file_parameters.block_size,
+            logical_sector_size: metadata.logical_sector_size,
+            virtual_disk_size: metadata.virtual_disk_size,
+            payload_blocks,
+            sector_bitmap_blocks,
+        })
+    }
+
+    /// Returns the number of payload blocks whose sectors one sector bitmap block describes:
+    /// 2^23 × logical sector size / block size.
+    pub fn chunk_ratio(&self) -> u64 {
+        self.chunk_ratio
+    }
+
+    /// Returns the entries of the payload blocks, in the order of the virtual disk.
+    pub fn payload_blocks(&self) -> &[PayloadBlock] {
+        &self.payload_blocks
+    }
+
+    /// Returns the entries of the sector bitmap blocks of a differencing disk, one per chunk of
+    /// [`VhdxBat::chunk_ratio`] payload blocks. Empty for other disks.
+    pub fn sector_bitmap_blocks(&self) -> &[SectorBitmapBlock] {
+        &self.sector_bitmap_blocks
+    }
+
+    /// Returns the index in the BAT of the entry of payload block `block`.
+    pub fn payload_entry_index(&self, block: u64) -> u64 {
+        block + block / self.chunk_ratio
+    }
+
+    /// Returns the index in the BAT of the entry of the sector bitmap block of chunk `chunk`.
+    pub fn sector_bitmap_entry_index(&self, chunk: u64) -> u64 {
+        chunk * (self.chunk_ratio + 1) + self.chunk_ratio
+    }
+
+    /// Replaces the entry of payload block `block`, once the BAT in the file points to it.
+    pub(crate) fn set_payload_block(&mut self, block: u64, entry: PayloadBlock) {
+        self.payload_blocks[block as usize] = entry;
+    }
+
+    /// Returns the range of the virtual disk that payload block `block` covers.
+    pub fn block_range(&self, block: u64) -> Range<u64> {
+        let start = block * u64::from(self.block_size);
+        start..(start + u64::from(self.block_size)).min(self.virtual_disk_size)
+    }
+
+    /// Returns the ranges of the virtual disk whose data is stored in the file, merging adjacent
+    /// ones. `sector_bitmap` returns the sector bitmap block of a chunk, which is only requested
+    /// for chunks with partially present blocks.
+    pub(crate) fn allocated_ranges<E>(
+        &self,
+        mut sector_bitmap: impl FnMut(&SectorBitmapBlock) -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<Range<u64>>, E> {
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        let mut push = |range: Range<u64>| match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        };
+
+        let sector_size = u64::from(self.logical_sector_size);
+        let sectors_per_block = u64::from(self.block_size) / sector_size;
+        let mut bitmap: Option<(u64, Vec<u8>)> = None;
+        for (block, entry) in (0..).zip(&self.payload_blocks) {
+            let range = self.block_range(block);
+            match entry.state {
+                PayloadState::FullyPresent => push(range),
+                PayloadState::PartiallyPresent => {
+                    let chunk = block / self.chunk_ratio;
+                    if bitmap.as_ref().map(|(loaded, _)| *loaded) != Some(chunk) {
+                        let bytes = sector_bitmap(&self.sector_bitmap_blocks[chunk as usize])?;
+                        bitmap = Some((chunk, bytes));
+                    }
+                    let (_, bytes) = bitmap.as_ref().unwrap();
+
+                    let first_sector = (block % self.chunk_ratio) * sectors_per_block;
+                    let sectors = (range.end - range.start).div_ceil(sector_size);
+                    for sector in 0..sectors {
+                        if bitmap_bit(bytes, first_sector + sector) {
+                            let start = range.start + sector * sector_size;
+                            push(start..(start + sector_size).min(range.end));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(ranges)
+    }
+}
+
+/// Returns the chunk ratio of a file with `metadata`.
+fn chunk_ratio(metadata: &VhdxMetadata) -> u64 {
+    SECTORS_PER_CHUNK * u64::from(metadata.logical_sector_size)
+        / u64::from(metadata.file_parameters.block_size)
+}
+
+/// Returns the number of payload blocks of a file with `metadata`.
+fn payload_block_count(metadata: &VhdxMetadata) -> u64 {
+    metadata
+        .virtual_disk_size
+        .div_ceil(u64::from(metadata.file_parameters.block_size))
+}
+
+/// Returns whether the bit of `sector` is set in a sector bitmap. Bits are ordered from the
+/// least significant bit of the first byte.
+pub(crate) fn bitmap_bit(bitmap: &[u8], sector: u64) -> bool {
+    bitmap[(sector / 8) as usize] & (1 << (sector % 8)) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::vhdx_metadata;
+
+    fn bat_bytes(entries: &[(u64, u64)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &(state, file_offset) in entries {
+            bytes.extend_from_slice(&(state | file_offset).to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn chunk_ratio_and_entry_count() {
+        // 2 MiB blocks of 512-byte sectors: 2048 blocks per chunk
+        let metadata = vhdx_metadata(10 << 30, 2 << 20);
+        assert_eq!(chunk_ratio(&metadata), 2048);
+        assert_eq!(VhdxBat::entry_count(&metadata), 5120 + 2);
+
+        let mut metadata = vhdx_metadata(10 << 30, 32 << 20);
+        metadata.logical_sector_size = 4096;
+        assert_eq!(chunk_ratio(&metadata), 1024);
+        assert_eq!(VhdxBat::entry_count(&metadata), 320);
+
+        metadata.file_parameters.has_parent = true;
+        assert_eq!(VhdxBat::entry_count(&metadata), 1025);
+    }
+
+    #[test]
+    fn interleaved_entries() {
+        // 256 MiB blocks: a chunk ratio of 16
+        let metadata = vhdx_metadata(20 * (256 << 20), 256 << 20);
+        let mut entries = vec![(0, 0); VhdxBat::entry_count(&metadata) as usize];
+        assert_eq!(entries.len(), 21);
+        entries[0] = (6, 4 * MIB);
+        entries[15] = (2, 0);
+        entries[17] = (6, 5 * MIB);
+        entries[20] = (3, 0);
+        let bat = VhdxBat::parse(&bat_bytes(&entries), 0, &metadata).unwrap();
+
+        assert_eq!(bat.chunk_ratio(), 16);
+        let blocks = bat.payload_blocks();
+        assert_eq!(blocks.len(), 20);
+        assert_eq!(
+            blocks[0],
+            PayloadBlock {
+                state: PayloadState::FullyPresent,
+                file_offset: 4 * MIB
+            }
+        );
+        assert_eq!(blocks[15].state, PayloadState::Zero);
+        assert_eq!(blocks[16].file_offset, 5 * MIB);
+        assert_eq!(blocks[19].state, PayloadState::Unmapped);
+        assert_eq!(bat.payload_entry_index(16), 17);
+        assert!(bat.sector_bitmap_blocks().is_empty());
+        assert_eq!(blocks[16].to_u64(), 6 | (5 * MIB));
+
+        let ranges = bat.allocated_ranges(|_| Ok::<_, ()>(Vec::new())).unwrap();
+        assert_eq!(ranges, [0..256 << 20, 16 * (256 << 20)..17 * (256 << 20)]);
+    }
+
+    #[test]
+    fn invalid_states() {
+        let metadata = vhdx_metadata(4 * MIB, MIB as u32);
+        let error = VhdxBat::parse(
+            &bat_bytes(&[(0, 0), (4, 0), (0, 0), (0, 0)]),
+            0x30_0000,
+            &metadata,
+        )
+        .unwrap_err();
+        assert_eq!(error.structure(), Structure::VhdxBatEntry);
+        assert_eq!(error.field(), "state");
+        assert_eq!(error.offset(), 0x30_0008);
+
+        let error = VhdxBat::parse(
+            &bat_bytes(&[(7, 4 * MIB), (0, 0), (0, 0), (0, 0)]),
+            0,
+            &metadata,
+        )
+        .unwrap_err();
+        assert_eq!(error.field(), "state");
+
+        let error = VhdxBat::parse(&bat_bytes(&[(6, 0), (0, 0), (0, 0), (0, 0)]), 0, &metadata)
+            .unwrap_err();
+        assert_eq!(error.field(), "file offset");
+    }
+
+    #[test]
+    fn partially_present_blocks() {
+        // 64 MiB blocks: a chunk ratio of 64, the sector bitmap entry is the 65th entry
+        let mut metadata = vhdx_metadata(2 * (64 << 20), 64 << 20);
+        metadata.file_parameters.has_parent = true;
+        let mut entries = vec![(0, 0); VhdxBat::entry_count(&metadata) as usize];
+        entries[1] = (7, 8 * MIB);
+        assert!(VhdxBat::parse(&bat_bytes(&entries), 0, &metadata).is_err());
+
+        entries[64] = (6, 7 * MIB);
+        let bat = VhdxBat::parse(&bat_bytes(&entries), 0, &metadata).unwrap();
+        assert_eq!(
+            bat.sector_bitmap_blocks(),
+            [SectorBitmapBlock {
+                present: true,
+                file_offset: 7 * MIB
+            }]
+        );
+        assert_eq!(bat.sector_bitmap_entry_index(0), 64);
+
+        // sectors 1 and 2 of block 1, which starts at sector 131072 of the chunk
+        let ranges = bat
+            .allocated_ranges(|block| {
+                assert_eq!(block.file_offset, 7 * MIB);
+                let mut bitmap = vec![0; MIB as usize];
+                bitmap[131_072 / 8] = 0b0000_0110;
+                Ok::<_, ()>(bitmap)
+            })
+            .unwrap();
+        let block_start = 64 << 20;
+        assert_eq!(ranges, vec![block_start + 512..block_start + 1536]);
+    }
+}
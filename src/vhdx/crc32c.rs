@@ -0,0 +1,61 @@
+//! CRC-32C (Castagnoli), the checksum of the VHDX headers, region tables and log entries.
+
+/// The reversed Castagnoli polynomial.
+const POLYNOMIAL: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Computes the CRC-32C of `bytes`.
+pub(crate) fn crc32c(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Computes the checksum of a structure whose 4-byte checksum field at `checksum_offset` is
+/// treated as zero.
+pub(crate) fn checksum(bytes: &[u8], checksum_offset: usize) -> u32 {
+    let mut copy = bytes.to_vec();
+    copy[checksum_offset..checksum_offset + 4].fill(0);
+    crc32c(&copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+    }
+
+    #[test]
+    fn checksum_ignores_field() {
+        let mut bytes = *b"head\xff\xff\xff\xff0123";
+        let expected = checksum(&bytes, 4);
+        bytes[4..8].fill(0);
+        assert_eq!(crc32c(&bytes), expected);
+    }
+}
@@ -0,0 +1,346 @@
+use uuid::Uuid;
+
+use super::crc32c::checksum;
+use super::{guid, le_u16, le_u32, le_u64};
+use crate::{ParseError, Structure};
+
+/// Signature of the file type identifier at offset 0.
+pub(crate) const FILE_SIGNATURE: &[u8; 8] = b"vhdxfile";
+/// Number of bytes of the file type identifier that are parsed: the signature and the creator.
+pub(crate) const FILE_IDENTIFIER_SIZE: usize = 8 + CREATOR_SIZE;
+/// Signature of the headers.
+const HEADER_SIGNATURE: &[u8; 4] = b"head";
+/// Size of a header, over which its checksum is computed.
+pub(crate) const HEADER_SIZE: usize = 4096;
+/// Offsets of the two headers.
+pub(crate) const HEADER_OFFSETS: [u64; 2] = [64 << 10, 128 << 10];
+/// Version 1 of the header, the only one defined by the specification.
+pub(crate) const HEADER_VERSION: u16 = 1;
+/// Alignment of the log and of most other structures of the file.
+pub(crate) const MIB: u64 = 1 << 20;
+
+const CREATOR: usize = 8;
+const CREATOR_SIZE: usize = 512;
+
+const CHECKSUM: usize = 4;
+const SEQUENCE_NUMBER: usize = 8;
+const FILE_WRITE_GUID: usize = 16;
+const DATA_WRITE_GUID: usize = 32;
+const LOG_GUID: usize = 48;
+const LOG_VERSION: usize = 64;
+const VERSION: usize = 66;
+pub(crate) const LOG_LENGTH: usize = 68;
+const LOG_OFFSET: usize = 72;
+
+/// The file type identifier at offset 0 of every VHDX file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileIdentifier {
+    /// Name of the application that created the file, e.g. `Microsoft Windows 10.0.19045.0`.
+    pub creator: String,
+}
+
+impl FileIdentifier {
+    /// Parses the file type identifier in `bytes`, which was read from offset 0.
+    ///
+    /// # Errors
+    /// If the signature is not `vhdxfile`.
+    pub fn parse(bytes: &[u8; FILE_IDENTIFIER_SIZE]) -> Result<Self, ParseError> {
+        if &bytes[..8] != FILE_SIGNATURE {
+            return Err(ParseError::mismatch(
+                Structure::FileIdentifier,
+                "signature",
+                0,
+                "\"vhdxfile\"",
+                format!("{:?}", String::from_utf8_lossy(&bytes[..8])),
+            ));
+        }
+
+        let creator: Vec<u16> = bytes[CREATOR..CREATOR + CREATOR_SIZE]
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+
+        Ok(FileIdentifier {
+            creator: String::from_utf16_lossy(&creator),
+        })
+    }
+
+    /// Serializes the file type identifier, truncating the creator to 255 UTF-16 code units.
+    pub fn to_bytes(&self) -> [u8; FILE_IDENTIFIER_SIZE] {
+        let mut bytes = [0; FILE_IDENTIFIER_SIZE];
+        bytes[..8].copy_from_slice(FILE_SIGNATURE);
+        for (i, unit) in self
+            .creator
+            .encode_utf16()
+            .take(CREATOR_SIZE / 2 - 1)
+            .enumerate()
+        {
+            let offset = CREATOR + i * 2;
+            bytes[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// One of the two 4 KiB headers at offsets 64 KiB and 128 KiB of a VHDX file.
+///
+/// The header with the higher sequence number of the two valid ones is the current one; see
+/// [`VhdxImage::header`](crate::VhdxImage::header). All fields are stored little-endian and the
+/// GUIDs in the layout Windows uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdxHeader {
+    /// The CRC-32C stored in the header. [`VhdxHeader::to_bytes`] recomputes it.
+    pub checksum: u32,
+    /// Incremented every time the header is rewritten.
+    pub sequence_number: u64,
+    /// Changed the first time the file is opened for writing after it was opened.
+    pub file_write_guid: Uuid,
+    /// Changed the first time the contents of the virtual disk are modified after the file was
+    /// opened.
+    pub data_write_guid: Uuid,
+    /// Identifies the entries of the log that are valid; nil if the log is empty.
+    pub log_guid: Uuid,
+    /// Version of the log format, 0 for the one of the specification.
+    pub log_version: u16,
+    /// Version of the header, 1 for the one of the specification.
+    pub version: u16,
+    /// Size of the log in bytes, a multiple of 1 MiB.
+    pub log_length: u32,
+    /// Absolute offset of the log, a multiple of 1 MiB.
+    pub log_offset: u64,
+}
+
+impl VhdxHeader {
+    /// Parses the header in `bytes`, which was read from file offset `offset`.
+    ///
+    /// # Errors
+    /// If the signature or checksum is invalid, which makes the header invalid as a whole.
+    pub fn parse(bytes: &[u8; HEADER_SIZE], offset: u64) -> Result<Self, ParseError> {
+        if &bytes[..4] != HEADER_SIGNATURE {
+            return Err(ParseError::mismatch(
+                Structure::VhdxHeader,
+                "signature",
+                offset,
+                "\"head\"",
+                format!("{:?}", String::from_utf8_lossy(&bytes[..4])),
+            ));
+        }
+
+        let stored_checksum = le_u32(bytes, CHECKSUM);
+        let computed_checksum = checksum(bytes, CHECKSUM);
+        if stored_checksum != computed_checksum {
+            return Err(ParseError::mismatch(
+                Structure::VhdxHeader,
+                "checksum",
+                offset + CHECKSUM as u64,
+                format!("{computed_checksum:#010x}"),
+                format!("{stored_checksum:#010x}"),
+            ));
+        }
+
+        Ok(VhdxHeader {
+            checksum: stored_checksum,
+            sequence_number: le_u64(bytes, SEQUENCE_NUMBER),
+            file_write_guid: guid(bytes, FILE_WRITE_GUID),
+            data_write_guid: guid(bytes, DATA_WRITE_GUID),
+            log_guid: guid(bytes, LOG_GUID),
+            log_version: le_u16(bytes, LOG_VERSION),
+            version: le_u16(bytes, VERSION),
+            log_length: le_u32(bytes, LOG_LENGTH),
+            log_offset: le_u64(bytes, LOG_OFFSET),
+        })
+    }
+
+    /// Checks the fields of the current header, which was read from file offset `offset`.
+    ///
+    /// # Errors
+    /// If the version or log version is unknown, or the log is not aligned to 1 MiB.
+    pub(crate) fn check(&self, offset: u64) -> Result<(), ParseError> {
+        let field_offset = |field: usize| offset + field as u64;
+
+        if self.version != HEADER_VERSION {
+            return Err(ParseError::mismatch(
+                Structure::VhdxHeader,
+                "version",
+                field_offset(VERSION),
+                HEADER_VERSION,
+                self.version,
+            ));
+        }
+        if self.log_version != 0 {
+            return Err(ParseError::mismatch(
+                Structure::VhdxHeader,
+                "log version",
+                field_offset(LOG_VERSION),
+                0,
+                self.log_version,
+            ));
+        }
+        if !u64::from(self.log_length).is_multiple_of(MIB) {
+            return Err(ParseError::mismatch(
+                Structure::VhdxHeader,
+                "log length",
+                field_offset(LOG_LENGTH),
+                "a multiple of 1 MiB",
+                self.log_length,
+            ));
+        }
+        if self.log_offset < MIB || !self.log_offset.is_multiple_of(MIB) {
+            return Err(ParseError::mismatch(
+                Structure::VhdxHeader,
+                "log offset",
+                field_offset(LOG_OFFSET),
+                "a non-zero multiple of 1 MiB",
+                self.log_offset,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Serializes the header, computing a fresh checksum. The stored [`VhdxHeader::checksum`]
+    /// is ignored.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(HEADER_SIGNATURE);
+        bytes[SEQUENCE_NUMBER..SEQUENCE_NUMBER + 8]
+            .copy_from_slice(&self.sequence_number.to_le_bytes());
+        bytes[FILE_WRITE_GUID..FILE_WRITE_GUID + 16]
+            .copy_from_slice(&self.file_write_guid.to_bytes_le());
+        bytes[DATA_WRITE_GUID..DATA_WRITE_GUID + 16]
+            .copy_from_slice(&self.data_write_guid.to_bytes_le());
+        bytes[LOG_GUID..LOG_GUID + 16].copy_from_slice(&self.log_guid.to_bytes_le());
+        bytes[LOG_VERSION..LOG_VERSION + 2].copy_from_slice(&self.log_version.to_le_bytes());
+        bytes[VERSION..VERSION + 2].copy_from_slice(&self.version.to_le_bytes());
+        bytes[LOG_LENGTH..LOG_LENGTH + 4].copy_from_slice(&self.log_length.to_le_bytes());
+        bytes[LOG_OFFSET..LOG_OFFSET + 8].copy_from_slice(&self.log_offset.to_le_bytes());
+
+        let checksum = checksum(&bytes, CHECKSUM);
+        bytes[CHECKSUM..CHECKSUM + 4].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+}
+
+/// Selects the current header from the results of parsing the two headers: the valid one with
+/// the higher sequence number. Returns its index.
+///
+/// # Errors
+/// The error of the first header if neither is valid, or an error if both are valid and have the
+/// same sequence number.
+pub(crate) fn current_header(
+    headers: [Result<VhdxHeader, ParseError>; 2],
+) -> Result<(usize, VhdxHeader), ParseError> {
+    let [first, second] = headers;
+    match (first, second) {
+        (Ok(first), Ok(second)) if first.sequence_number == second.sequence_number => {
+            Err(ParseError::mismatch(
+                Structure::VhdxHeader,
+                "sequence number",
+                HEADER_OFFSETS[1] + SEQUENCE_NUMBER as u64,
+                format!(
+                    "not the sequence number {} of the first header",
+                    first.sequence_number
+                ),
+                second.sequence_number,
+            ))
+        }
+        (Ok(first), Ok(second)) if second.sequence_number > first.sequence_number => {
+            Ok((1, second))
+        }
+        (Ok(first), _) => Ok((0, first)),
+        (Err(_), Ok(second)) => Ok((1, second)),
+        (Err(error), Err(_)) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(sequence_number: u64) -> VhdxHeader {
+        VhdxHeader {
+            checksum: 0,
+            sequence_number,
+            file_write_guid: Uuid::from_u128(0x0011_2233_4455_6677_8899_aabb_ccdd_eeff),
+            data_write_guid: Uuid::from_u128(2),
+            log_guid: Uuid::nil(),
+            log_version: 0,
+            version: HEADER_VERSION,
+            log_length: MIB as u32,
+            log_offset: MIB,
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let bytes = header(7).to_bytes();
+        let parsed = VhdxHeader::parse(&bytes, HEADER_OFFSETS[0]).unwrap();
+        assert_eq!(parsed.checksum, checksum(&bytes, CHECKSUM));
+        assert_eq!(
+            VhdxHeader {
+                checksum: 0,
+                ..parsed
+            },
+            header(7)
+        );
+        assert_eq!(&bytes[16..20], &[0x33, 0x22, 0x11, 0x00]);
+    }
+
+    #[test]
+    fn header_checksum() {
+        let mut bytes = header(1).to_bytes();
+        bytes[LOG_OFFSET] ^= 1;
+        let error = VhdxHeader::parse(&bytes, HEADER_OFFSETS[1]).unwrap_err();
+        assert_eq!(error.structure(), Structure::VhdxHeader);
+        assert_eq!(error.field(), "checksum");
+        assert_eq!(error.offset(), HEADER_OFFSETS[1] + 4);
+    }
+
+    #[test]
+    fn header_check() {
+        assert_eq!(header(1).check(0), Ok(()));
+
+        let error = VhdxHeader {
+            version: 2,
+            ..header(1)
+        }
+        .check(0)
+        .unwrap_err();
+        assert_eq!(error.field(), "version");
+
+        let error = VhdxHeader {
+            log_offset: MIB + 4096,
+            ..header(1)
+        }
+        .check(0)
+        .unwrap_err();
+        assert_eq!(error.field(), "log offset");
+    }
+
+    #[test]
+    fn current_header_selection() {
+        let invalid = || Err(ParseError::new(Structure::VhdxHeader, "signature", 0));
+
+        assert_eq!(current_header([Ok(header(1)), Ok(header(2))]).unwrap().0, 1);
+        assert_eq!(current_header([Ok(header(3)), Ok(header(2))]).unwrap().0, 0);
+        assert!(current_header([Ok(header(2)), Ok(header(2))]).is_err());
+        assert_eq!(current_header([invalid(), Ok(header(1))]).unwrap().0, 1);
+        assert_eq!(current_header([Ok(header(1)), invalid()]).unwrap().0, 0);
+        assert!(current_header([invalid(), invalid()]).is_err());
+    }
+
+    #[test]
+    fn file_identifier() {
+        let identifier = FileIdentifier {
+            creator: "vhdrs 0.1".to_owned(),
+        };
+        let bytes = identifier.to_bytes();
+        assert_eq!(&bytes[8..12], &[b'v', 0, b'h', 0]);
+        assert_eq!(FileIdentifier::parse(&bytes).unwrap(), identifier);
+
+        let mut bytes = bytes;
+        bytes[0] = b'V';
+        let error = FileIdentifier::parse(&bytes).unwrap_err();
+        assert_eq!(error.structure(), Structure::FileIdentifier);
+    }
+}
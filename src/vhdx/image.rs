@@ -0,0 +1,1241 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use super::bat::{PayloadBlock, PayloadState, ENTRY_SIZE, SECTOR_BITMAP_BLOCK_SIZE};
+use super::header::{
+    current_header, FILE_IDENTIFIER_SIZE, HEADER_OFFSETS, HEADER_SIZE, LOG_LENGTH, MIB,
+};
+use super::log::{apply_writes, LogWrite, LogWriter, Replay, LOG_SECTOR_SIZE};
+use super::metadata::{KNOWN_ITEMS, METADATA_TABLE_SIZE};
+use super::region::{BAT_REGION, METADATA_REGION, REGION_TABLE_OFFSETS, REGION_TABLE_SIZE};
+use super::{FileIdentifier, MetadataTable, RegionTable, VhdxBat, VhdxHeader, VhdxMetadata};
+use crate::vhd::{read_exact_at, seek_position, write_all_at};
+use crate::{Error, ErrorKind, OpenMode, ParseError, Result, Structure};
+
+/// Regions defined by the specification.
+const KNOWN_REGIONS: [Uuid; 2] = [BAT_REGION, METADATA_REGION];
+
+/// A VHDX file opened with the pure-Rust implementation of the format.
+///
+/// Implements [`Read`], [`Write`] and [`Seek`] over the virtual disk of fixed and dynamic VHDX
+/// files, which is [`VhdxImage::virtual_size`] bytes long. Blocks that are not present, zero or
+/// unmapped read as zeros; writing to one allocates a new block at the end of the file.
+/// Differencing VHDX files cannot be read or written, as their parent is not opened.
+///
+/// The file write GUID of the header is replaced before the first change to the file, and the
+/// data write GUID before the first change to the virtual disk, as the specification requires.
+///
+/// A log left behind by a crash is replayed when the file is opened: to the file if it is
+/// opened for writing, otherwise in memory, so that the file is left untouched. Updates of the
+/// metadata of a file opened for writing go through the log, which is emptied again when the
+/// image is dropped.
+#[derive(Debug)]
+pub struct VhdxImage {
+    storage: Storage,
+    path: PathBuf,
+    mode: OpenMode,
+    identifier: FileIdentifier,
+    header: VhdxHeader,
+    /// Index in [`HEADER_OFFSETS`] of the current header.
+    header_index: usize,
+    region_table: RegionTable,
+    metadata: VhdxMetadata,
+    bat: VhdxBat,
+    log_replayed: bool,
+    /// The log metadata updates are written through, once the first one was made.
+    log: Option<LogWriter>,
+    /// Whether the file write GUID was replaced since the file was opened.
+    file_write_guid_updated: bool,
+    /// Whether the data write GUID was replaced since the file was opened.
+    data_write_guid_updated: bool,
+    position: u64,
+}
+
+/// The VHDX file, read through the log replayed in memory if there is one.
+#[derive(Debug)]
+struct Storage {
+    file: File,
+    replay: Option<Replay>,
+}
+
+impl Storage {
+    /// Reads exactly `buf.len()` bytes at `offset`.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match &self.replay {
+            Some(replay) => replay.read_at(&self.file, offset, buf),
+            None => read_exact_at(&self.file, offset, buf),
+        }
+    }
+}
+
+impl VhdxImage {
+    /// Opens the VHDX file at `path`, validates its file type identifier, selects the current
+    /// header and parses the region table, the metadata region and the BAT.
+    ///
+    /// Both headers and both copies of the region table are checked with their CRC-32C; the
+    /// valid header with the higher sequence number is the current one, and the first valid copy
+    /// of the region table is used. If the header has a log GUID, the active sequence of the log
+    /// is replayed before anything else is read.
+    ///
+    /// # Errors
+    /// If the file cannot be opened or read, its file type identifier, both headers, both region
+    /// tables, the log or the metadata are invalid, or both headers are valid with the same
+    /// sequence number ([`ErrorKind::Corrupt`]), or it has a required region or metadata item
+    /// this implementation does not know ([`ErrorKind::Unsupported`]).
+    pub fn open<P: AsRef<Path>>(path: P, open_mode: OpenMode) -> Result<Self> {
+        let path = path.as_ref();
+        Self::open_file(path, open_mode).map_err(|e| e.with_path(path))
+    }
+
+    fn open_file(path: &Path, open_mode: OpenMode) -> Result<Self> {
+        let io_error = |e| Error::io("open", e);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(matches!(open_mode, OpenMode::ReadWrite))
+            .open(path)
+            .map_err(io_error)?;
+
+        let mut bytes = [0; FILE_IDENTIFIER_SIZE];
+        read_exact_at(&file, 0, &mut bytes).map_err(io_error)?;
+        let identifier = FileIdentifier::parse(&bytes).map_err(|e| Error::corrupt("open", e))?;
+
+        let mut headers = Vec::with_capacity(2);
+        for offset in HEADER_OFFSETS {
+            let mut bytes = [0; HEADER_SIZE];
+            read_exact_at(&file, offset, &mut bytes).map_err(io_error)?;
+            headers.push(VhdxHeader::parse(&bytes, offset));
+        }
+        let headers = headers.try_into().unwrap();
+        let (mut header_index, mut header) =
+            current_header(headers).map_err(|e| Error::corrupt("open", e))?;
+        header
+            .check(HEADER_OFFSETS[header_index])
+            .map_err(|e| Error::corrupt("open", e))?;
+
+        let mut storage = Storage { file, replay: None };
+        let log_replayed = !header.log_guid.is_nil();
+        let mut file_write_guid_updated = false;
+        if log_replayed {
+            let file_size = storage.file.metadata().map_err(io_error)?.len();
+            let log_end = header
+                .log_offset
+                .saturating_add(u64::from(header.log_length));
+            if log_end > file_size {
+                return Err(Error::corrupt(
+                    "open",
+                    ParseError::mismatch(
+                        Structure::VhdxHeader,
+                        "log length",
+                        HEADER_OFFSETS[header_index] + LOG_LENGTH as u64,
+                        format!("a log ending within the file size {file_size}"),
+                        format!("a log ending at {log_end}"),
+                    ),
+                ));
+            }
+
+            let replay = Replay::find(
+                &storage.file,
+                header.log_offset,
+                header.log_length,
+                header.log_guid,
+            )?;
+            if file_size < replay.flushed_file_offset {
+                return Err(Error::corrupt(
+                    "open",
+                    ParseError::mismatch(
+                        Structure::LogEntry,
+                        "flushed file offset",
+                        header.log_offset,
+                        format!("at most the file size {file_size}"),
+                        replay.flushed_file_offset,
+                    ),
+                ));
+            }
+
+            match open_mode {
+                OpenMode::ReadWrite => {
+                    replay.apply(&storage.file).map_err(io_error)?;
+                    // the replay changed the file, so the file write GUID changes as well
+                    let cleared = VhdxHeader {
+                        file_write_guid: Uuid::new_v4(),
+                        log_guid: Uuid::nil(),
+                        ..header
+                    };
+                    (header_index, header) =
+                        write_header(&storage.file, header_index, cleared).map_err(io_error)?;
+                    file_write_guid_updated = true;
+                }
+                OpenMode::ReadOnly => storage.replay = Some(replay),
+            }
+        }
+
+        let region_table = read_region_table(&storage)?;
+        let metadata = read_metadata(&storage, &region_table)?;
+        let bat = read_bat(&storage, &region_table, &metadata)?;
+
+        Ok(VhdxImage {
+            storage,
+            path: path.to_path_buf(),
+            mode: open_mode,
+            identifier,
+            header,
+            header_index,
+            region_table,
+            metadata,
+            bat,
+            log_replayed,
+            log: None,
+            file_write_guid_updated,
+            data_write_guid_updated: false,
+            position: 0,
+        })
+    }
+
+    /// Returns the path the image was opened from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the mode the image was opened in.
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+
+    /// Returns the file type identifier.
+    pub fn identifier(&self) -> &FileIdentifier {
+        &self.identifier
+    }
+
+    /// Returns the current header: the valid header with the higher sequence number.
+    pub fn header(&self) -> &VhdxHeader {
+        &self.header
+    }
+
+    /// Returns the region table.
+    pub fn region_table(&self) -> &RegionTable {
+        &self.region_table
+    }
+
+    /// Returns the well-known items of the metadata region.
+    pub fn metadata(&self) -> &VhdxMetadata {
+        &self.metadata
+    }
+
+    /// Returns the Block Allocation Table.
+    pub fn bat(&self) -> &VhdxBat {
+        &self.bat
+    }
+
+    /// Returns the ranges of the virtual disk whose data is stored in the file, in order and
+    /// with adjacent ranges merged: the fully present blocks, and the sectors of partially
+    /// present blocks that are marked in their sector bitmap.
+    ///
+    /// # Errors
+    /// If a sector bitmap block cannot be read.
+    pub fn allocated_ranges(&self) -> Result<Vec<Range<u64>>> {
+        self.bat
+            .allocated_ranges(|block| {
+                let mut bitmap = vec![0; SECTOR_BITMAP_BLOCK_SIZE as usize];
+                self.storage.read_at(block.file_offset, &mut bitmap)?;
+                Ok(bitmap)
+            })
+            .map_err(|e| Error::io("read", e).with_path(&self.path))
+    }
+
+    /// Returns the size of the virtual disk in bytes.
+    pub fn virtual_size(&self) -> u64 {
+        self.metadata.virtual_disk_size
+    }
+
+    /// Returns the size of a payload block in bytes.
+    pub fn block_size(&self) -> u32 {
+        self.metadata.file_parameters.block_size
+    }
+
+    /// Returns the size of the VHDX file in bytes.
+    ///
+    /// # Errors
+    /// If the metadata of the file cannot be queried.
+    pub fn physical_size(&self) -> Result<u64> {
+        self.storage
+            .file
+            .metadata()
+            .map(|metadata| metadata.len())
+            .map_err(|e| Error::io("get size", e).with_path(&self.path))
+    }
+
+    /// Returns the file offset of the current header, 64 KiB or 128 KiB.
+    pub fn header_offset(&self) -> u64 {
+        HEADER_OFFSETS[self.header_index]
+    }
+
+    /// Returns whether a log was replayed when the image was opened, which means the file was
+    /// not closed cleanly.
+    pub fn log_replayed(&self) -> bool {
+        self.log_replayed
+    }
+
+    /// Reads from the virtual disk at `offset`, returning the number of bytes read. Stops at the
+    /// end of the virtual disk.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if self.metadata.file_parameters.has_parent {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "differencing VHDX images cannot be read",
+            ));
+        }
+
+        let remaining = self.virtual_size().saturating_sub(offset);
+        let len = (buf.len() as u64).min(remaining) as usize;
+        let block_size = u64::from(self.block_size());
+
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let block = self.bat.payload_blocks()[(position / block_size) as usize];
+            let in_block = position % block_size;
+            let chunk = ((block_size - in_block) as usize).min(len - done);
+            let target = &mut buf[done..done + chunk];
+
+            match block.state {
+                PayloadState::FullyPresent => {
+                    self.storage.read_at(block.file_offset + in_block, target)?
+                }
+                // only differencing disks have partially present blocks
+                PayloadState::PartiallyPresent => unreachable!(),
+                PayloadState::NotPresent
+                | PayloadState::Undefined
+                | PayloadState::Zero
+                | PayloadState::Unmapped => target.fill(0),
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// Writes to the virtual disk at `offset`, returning the number of bytes written. Stops at
+    /// the end of the virtual disk.
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<usize> {
+        if matches!(self.mode, OpenMode::ReadOnly) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the image is opened read-only",
+            ));
+        }
+        if self.metadata.file_parameters.has_parent {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "differencing VHDX images cannot be written",
+            ));
+        }
+
+        let remaining = self.virtual_size().saturating_sub(offset);
+        let len = (buf.len() as u64).min(remaining) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        self.update_write_guids()?;
+        let block_size = u64::from(self.block_size());
+
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let block = position / block_size;
+            let in_block = position % block_size;
+            let chunk = ((block_size - in_block) as usize).min(len - done);
+
+            let entry = self.bat.payload_blocks()[block as usize];
+            let file_offset = match entry.state {
+                PayloadState::FullyPresent => entry.file_offset,
+                _ => self.allocate_block(block)?,
+            };
+            write_all_at(
+                &self.storage.file,
+                file_offset + in_block,
+                &buf[done..done + chunk],
+            )?;
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// Allocates payload block `block` at the first 1 MiB boundary at or after the end of the
+    /// file and returns its offset.
+    ///
+    /// The file is first extended, so the block reads as zeros wherever it is not written, and
+    /// only then is the BAT entry updated through the log. An interrupted allocation leaves
+    /// unused space at the end of the file.
+    fn allocate_block(&mut self, block: u64) -> io::Result<u64> {
+        let file = &self.storage.file;
+        let block_offset = file.metadata()?.len().next_multiple_of(MIB);
+        file.set_len(block_offset + u64::from(self.block_size()))?;
+        file.sync_data()?;
+
+        let entry = PayloadBlock {
+            state: PayloadState::FullyPresent,
+            file_offset: block_offset,
+        };
+        let bat_offset = self.region_table.region(BAT_REGION).unwrap().file_offset;
+        let entry_offset = bat_offset + self.bat.payload_entry_index(block) * ENTRY_SIZE as u64;
+        self.journal(entry_offset, &entry.to_u64().to_le_bytes())?;
+        self.bat.set_payload_block(block, entry);
+        Ok(block_offset)
+    }
+
+    /// Writes the header with a new data write GUID before the first change to the virtual
+    /// disk, and a new file write GUID if this is also the first change to the file.
+    fn update_write_guids(&mut self) -> io::Result<()> {
+        if self.data_write_guid_updated {
+            return Ok(());
+        }
+
+        let mut header = VhdxHeader {
+            data_write_guid: Uuid::new_v4(),
+            ..self.header.clone()
+        };
+        if !self.file_write_guid_updated {
+            header.file_write_guid = Uuid::new_v4();
+        }
+        (self.header_index, self.header) =
+            write_header(&self.storage.file, self.header_index, header)?;
+        self.file_write_guid_updated = true;
+        self.data_write_guid_updated = true;
+        Ok(())
+    }
+
+    /// Writes `bytes` at `offset` of the file through the log, then parses the region table, the
+    /// metadata region and the BAT again.
+    ///
+    /// This is a low-level operation to patch the structures of the file, e.g. an item of the
+    /// metadata region, such that a crash at any point leaves either the old or the new bytes.
+    ///
+    /// # Errors
+    /// An [`ErrorKind::AccessDenied`] error if the image is opened read-only, an
+    /// [`ErrorKind::InvalidInput`] error if the range overlaps the file type identifier, the
+    /// headers or the log or ends past the largest file offset, or an error if the file cannot be
+    /// written or the structures are no longer valid.
+    pub fn write_metadata(&mut self, offset: u64, bytes: &[u8]) -> Result<()> {
+        if matches!(self.mode, OpenMode::ReadOnly) {
+            return Err(Error::new(ErrorKind::AccessDenied, "write")
+                .with_path(&self.path)
+                .with_source("the image is opened read-only"));
+        }
+
+        // the log covers whole sectors, so the end must also round up to one
+        let end = offset
+            .checked_add(bytes.len() as u64)
+            .filter(|end| {
+                end.checked_next_multiple_of(LOG_SECTOR_SIZE as u64)
+                    .is_some()
+            })
+            .ok_or_else(|| {
+                Error::new(ErrorKind::InvalidInput, "write")
+                    .with_path(&self.path)
+                    .with_source(format!(
+                        "the range of {} bytes at {offset:#x} exceeds the largest file offset",
+                        bytes.len()
+                    ))
+            })?;
+        let log_start = self.header.log_offset;
+        let log_end = log_start.saturating_add(u64::from(self.header.log_length));
+        if offset < REGION_TABLE_OFFSETS[0] || (offset < log_end && end > log_start) {
+            return Err(Error::new(ErrorKind::InvalidInput, "write")
+                .with_path(&self.path)
+                .with_source(format!(
+                    "the range {offset:#x}..{end:#x} overlaps the headers or the log"
+                )));
+        }
+
+        self.journal(offset, bytes)
+            .map_err(|e| Error::io("write", e).with_path(&self.path))?;
+
+        self.region_table =
+            read_region_table(&self.storage).map_err(|e| e.with_path(&self.path))?;
+        self.metadata = read_metadata(&self.storage, &self.region_table)
+            .map_err(|e| e.with_path(&self.path))?;
+        self.bat = read_bat(&self.storage, &self.region_table, &self.metadata)
+            .map_err(|e| e.with_path(&self.path))?;
+        Ok(())
+    }
+
+    /// Writes `bytes` at `offset` of the file through the log: the 4 KiB sectors they cover
+    /// are recorded in a log entry, which is flushed before the sectors are written in place and
+    /// flushed in turn.
+    pub(crate) fn journal(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let sector_size = LOG_SECTOR_SIZE as u64;
+        let start = offset / sector_size * sector_size;
+        let end = (offset + bytes.len() as u64).next_multiple_of(sector_size);
+        let mut sectors = vec![0; (end - start) as usize];
+        self.storage.read_at(start, &mut sectors)?;
+        sectors[(offset - start) as usize..][..bytes.len()].copy_from_slice(bytes);
+
+        let writes: Vec<LogWrite> = (start..)
+            .step_by(LOG_SECTOR_SIZE)
+            .zip(sectors.chunks_exact(LOG_SECTOR_SIZE))
+            .map(|(file_offset, data)| LogWrite::Data {
+                file_offset,
+                data: data.into(),
+            })
+            .collect();
+
+        self.open_log()?;
+        let file = &self.storage.file;
+        self.log
+            .as_mut()
+            .unwrap()
+            .write_entry(file, writes.clone())?;
+        apply_writes(file, &writes)?;
+        file.sync_data()
+    }
+
+    /// Starts a log with a new log GUID, recorded in the header, unless there is one already.
+    /// The same header write replaces the file write GUID if this is the first change to the
+    /// file.
+    fn open_log(&mut self) -> io::Result<()> {
+        if self.log.is_some() {
+            return Ok(());
+        }
+
+        let log_guid = Uuid::new_v4();
+        let mut header = VhdxHeader {
+            log_guid,
+            ..self.header.clone()
+        };
+        if !self.file_write_guid_updated {
+            header.file_write_guid = Uuid::new_v4();
+        }
+        (self.header_index, self.header) =
+            write_header(&self.storage.file, self.header_index, header)?;
+        self.file_write_guid_updated = true;
+        self.log = Some(LogWriter::new(
+            self.header.log_offset,
+            self.header.log_length,
+            log_guid,
+        ));
+        Ok(())
+    }
+}
+
+impl Read for VhdxImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for VhdxImage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.write_at(self.position, buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.storage.file.sync_data()
+    }
+}
+
+impl Seek for VhdxImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(self.position, self.virtual_size(), pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Empties the log: every entry written to it was applied and flushed, so it must not be
+/// replayed when the file is opened again. Errors are ignored; the file then replays entries
+/// that are already applied, which is harmless.
+impl Drop for VhdxImage {
+    fn drop(&mut self) {
+        if self.log.take().is_some() {
+            let header = VhdxHeader {
+                log_guid: Uuid::nil(),
+                ..self.header.clone()
+            };
+            let _ = write_header(&self.storage.file, self.header_index, header);
+        }
+    }
+}
+
+/// Writes `header` over the header that is not the current one, the one at `current_index`,
+/// with the next sequence number. Returns the index and the header that are now current.
+fn write_header(
+    file: &File,
+    current_index: usize,
+    header: VhdxHeader,
+) -> io::Result<(usize, VhdxHeader)> {
+    let index = 1 - current_index;
+    let header = VhdxHeader {
+        sequence_number: header.sequence_number + 1,
+        ..header
+    };
+    let bytes = header.to_bytes();
+    write_all_at(file, HEADER_OFFSETS[index], &bytes)?;
+    file.sync_data()?;
+    Ok((
+        index,
+        VhdxHeader {
+            checksum: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            ..header
+        },
+    ))
+}
+
+/// Reads the region table from the first of its two copies that is valid, and checks that it
+/// only has known required regions.
+fn read_region_table(storage: &Storage) -> Result<RegionTable> {
+    let mut bytes = vec![0; REGION_TABLE_SIZE];
+    let mut tables = REGION_TABLE_OFFSETS.iter().map(|&offset| {
+        storage
+            .read_at(offset, &mut bytes)
+            .map_err(|e| Error::io("open", e))?;
+        Ok(RegionTable::parse(bytes[..].try_into().unwrap(), offset))
+    });
+    let table = match tables.next().unwrap()? {
+        Ok(table) => table,
+        Err(error) => match tables.next().unwrap()? {
+            Ok(table) => table,
+            Err(_) => return Err(Error::corrupt("open", error)),
+        },
+    };
+
+    if let Some(region) = table
+        .entries
+        .iter()
+        .find(|region| region.required && !KNOWN_REGIONS.contains(&region.guid))
+    {
+        return Err(Error::new(ErrorKind::Unsupported, "open")
+            .with_source(format!("unknown required region {}", region.guid)));
+    }
+    for (guid, name) in [
+        (BAT_REGION, "a BAT region"),
+        (METADATA_REGION, "a metadata region"),
+    ] {
+        if table.region(guid).is_none() {
+            return Err(Error::corrupt(
+                "open",
+                ParseError::new(Structure::RegionTable, "entries", REGION_TABLE_OFFSETS[0])
+                    .with_expected(name),
+            ));
+        }
+    }
+    Ok(table)
+}
+
+/// Reads the metadata region located by `region_table` and decodes its well-known items.
+fn read_metadata(storage: &Storage, region_table: &RegionTable) -> Result<VhdxMetadata> {
+    let region = region_table.region(METADATA_REGION).unwrap();
+    let length = region.length as usize;
+    if length < METADATA_TABLE_SIZE {
+        return Err(Error::corrupt(
+            "open",
+            ParseError::mismatch(
+                Structure::RegionTable,
+                "metadata region length",
+                REGION_TABLE_OFFSETS[0],
+                format!("at least {METADATA_TABLE_SIZE}"),
+                length,
+            ),
+        ));
+    }
+
+    let mut bytes = vec![0; length];
+    storage
+        .read_at(region.file_offset, &mut bytes)
+        .map_err(|e| Error::io("open", e))?;
+    let table = MetadataTable::parse(
+        bytes[..METADATA_TABLE_SIZE].try_into().unwrap(),
+        region.file_offset,
+    )
+    .map_err(|e| Error::corrupt("open", e))?;
+
+    if let Some(item) = table
+        .entries
+        .iter()
+        .find(|item| item.required && !KNOWN_ITEMS.contains(&item.item_id))
+    {
+        return Err(Error::new(ErrorKind::Unsupported, "open")
+            .with_source(format!("unknown required metadata item {}", item.item_id)));
+    }
+
+    VhdxMetadata::parse(&table, &bytes, region.file_offset).map_err(|e| Error::corrupt("open", e))
+}
+
+/// Reads the BAT located by `region_table` of a file with `metadata`.
+fn read_bat(
+    storage: &Storage,
+    region_table: &RegionTable,
+    metadata: &VhdxMetadata,
+) -> Result<VhdxBat> {
+    let region = region_table.region(BAT_REGION).unwrap();
+    let length = VhdxBat::entry_count(metadata) * ENTRY_SIZE as u64;
+    if u64::from(region.length) < length {
+        return Err(Error::corrupt(
+            "open",
+            ParseError::mismatch(
+                Structure::RegionTable,
+                "BAT region length",
+                REGION_TABLE_OFFSETS[0],
+                format!("at least {length}"),
+                region.length,
+            ),
+        ));
+    }
+
+    let mut bytes = vec![0; length as usize];
+    storage
+        .read_at(region.file_offset, &mut bytes)
+        .map_err(|e| Error::io("open", e))?;
+    VhdxBat::parse(&bytes, region.file_offset, metadata).map_err(|e| Error::corrupt("open", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        vhdx, vhdx_bytes, vhdx_header, vhdx_metadata, vhdx_with_blocks, TempFile,
+    };
+    use crate::vhdx::log::{entry_bytes, LogEntry, LogWrite};
+    use crate::{
+        FileParameters, MetadataTableEntry, PayloadState, RegionTableEntry, VhdxParentLocator,
+    };
+
+    /// Returns a VHDX whose log allocates block 0 at 4 MiB and extends the file to 5 MiB.
+    fn vhdx_with_log() -> Vec<u8> {
+        let mut bytes = vhdx_bytes(&vhdx_metadata(4 * MIB, 1 << 20));
+        let mut bat_sector = bytes[3 << 20..(3 << 20) + 4096].to_vec();
+        bat_sector[..8].copy_from_slice(&(6 | (4 * MIB)).to_le_bytes());
+        let entry = LogEntry {
+            sequence_number: 10,
+            tail: 0,
+            length: 0,
+            flushed_file_offset: 4 * MIB,
+            last_file_offset: 5 * MIB,
+            writes: vec![LogWrite::Data {
+                file_offset: 3 * MIB,
+                data: bat_sector.into_boxed_slice(),
+            }],
+        };
+        let log_guid = Uuid::from_u128(0x10);
+        let entry = entry_bytes(&entry, log_guid);
+        bytes[1 << 20..(1 << 20) + entry.len()].copy_from_slice(&entry);
+
+        let header = VhdxHeader {
+            log_guid,
+            ..vhdx_header(1)
+        };
+        set_header(&mut bytes, 1, Some(header));
+        bytes
+    }
+
+    /// Replaces the header at `index` of `bytes` by `header`, or by garbage if `None`.
+    fn set_header(bytes: &mut [u8], index: usize, header: Option<VhdxHeader>) {
+        let offset = HEADER_OFFSETS[index] as usize;
+        match header {
+            Some(header) => bytes[offset..offset + HEADER_SIZE].copy_from_slice(&header.to_bytes()),
+            None => bytes[offset..offset + HEADER_SIZE].fill(0xee),
+        }
+    }
+
+    fn open_bytes(bytes: &[u8]) -> Result<VhdxImage> {
+        let file = TempFile::with_contents("image.vhdx", bytes);
+        VhdxImage::open(file.path(), OpenMode::ReadOnly)
+    }
+
+    #[test]
+    fn open() {
+        let file = vhdx(8 * MIB, 2 << 20);
+        let image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert_eq!(image.identifier().creator, "test");
+        assert_eq!(image.header().sequence_number, 1);
+        assert_eq!(image.header_offset(), 128 << 10);
+        assert_eq!(image.header().log_offset, MIB);
+        assert_eq!(image.physical_size().unwrap(), 4 * MIB);
+        assert_eq!(
+            image.region_table().region(BAT_REGION).unwrap().file_offset,
+            3 * MIB
+        );
+        assert_eq!(image.metadata(), &vhdx_metadata(8 * MIB, 2 << 20));
+        assert_eq!(image.virtual_size(), 8 * MIB);
+        assert_eq!(image.block_size(), 2 << 20);
+    }
+
+    #[test]
+    fn header_selection() {
+        let mut bytes = vhdx_bytes(&vhdx_metadata(MIB, 1 << 20));
+        set_header(&mut bytes, 0, Some(vhdx_header(7)));
+        let image = open_bytes(&bytes).unwrap();
+        assert_eq!(image.header().sequence_number, 7);
+        assert_eq!(image.header_offset(), 64 << 10);
+
+        set_header(&mut bytes, 0, None);
+        let image = open_bytes(&bytes).unwrap();
+        assert_eq!(image.header().sequence_number, 1);
+
+        set_header(&mut bytes, 1, None);
+        let error = open_bytes(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.structure(), Structure::VhdxHeader);
+        assert_eq!(parse_error.offset(), 64 << 10);
+    }
+
+    #[test]
+    fn unknown_header_version() {
+        let mut bytes = vhdx_bytes(&vhdx_metadata(MIB, 1 << 20));
+        let header = VhdxHeader {
+            version: 2,
+            ..vhdx_header(9)
+        };
+        set_header(&mut bytes, 0, Some(header));
+        let error = open_bytes(&bytes).unwrap_err();
+        assert_eq!(error.parse_error().unwrap().field(), "version");
+    }
+
+    #[test]
+    fn region_table_copy() {
+        let mut bytes = vhdx_bytes(&vhdx_metadata(MIB, 1 << 20));
+        let first = REGION_TABLE_OFFSETS[0] as usize;
+        bytes[first + 20] ^= 1;
+        assert!(open_bytes(&bytes).is_ok());
+
+        let second = REGION_TABLE_OFFSETS[1] as usize;
+        bytes[second + 20] ^= 1;
+        let error = open_bytes(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.structure(), Structure::RegionTable);
+        assert_eq!(parse_error.offset(), REGION_TABLE_OFFSETS[0] + 4);
+    }
+
+    #[test]
+    fn unknown_required_region() {
+        let mut bytes = vhdx_bytes(&vhdx_metadata(MIB, 1 << 20));
+        let offset = REGION_TABLE_OFFSETS[0] as usize;
+        let mut table = RegionTable::parse(
+            bytes[offset..offset + REGION_TABLE_SIZE]
+                .try_into()
+                .unwrap(),
+            0,
+        )
+        .unwrap();
+        let mut unknown = RegionTableEntry {
+            guid: Uuid::from_u128(0x1234),
+            file_offset: 4 * MIB,
+            length: MIB as u32,
+            required: false,
+        };
+        table.entries.push(unknown);
+        bytes[offset..offset + REGION_TABLE_SIZE].copy_from_slice(&table.to_bytes()[..]);
+        assert!(open_bytes(&bytes).is_ok());
+
+        unknown.required = true;
+        *table.entries.last_mut().unwrap() = unknown;
+        bytes[offset..offset + REGION_TABLE_SIZE].copy_from_slice(&table.to_bytes()[..]);
+        let error = open_bytes(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn unknown_required_metadata_item() {
+        let mut bytes = vhdx_bytes(&vhdx_metadata(MIB, 1 << 20));
+        let offset = 2 * MIB as usize;
+        let mut table = MetadataTable::parse(
+            bytes[offset..offset + METADATA_TABLE_SIZE]
+                .try_into()
+                .unwrap(),
+            0,
+        )
+        .unwrap();
+        let mut unknown = MetadataTableEntry {
+            item_id: Uuid::from_u128(0x1234),
+            offset: 128 << 10,
+            length: 8,
+            is_user: false,
+            is_virtual_disk: false,
+            required: false,
+        };
+        table.entries.push(unknown);
+        bytes[offset..offset + METADATA_TABLE_SIZE].copy_from_slice(&table.to_bytes()[..]);
+        assert!(open_bytes(&bytes).is_ok());
+
+        unknown.required = true;
+        *table.entries.last_mut().unwrap() = unknown;
+        bytes[offset..offset + METADATA_TABLE_SIZE].copy_from_slice(&table.to_bytes()[..]);
+        let error = open_bytes(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn allocated_ranges() {
+        let mut bytes = vhdx_bytes(&vhdx_metadata(4 * MIB, 1 << 20));
+        let entry = (6 | (4 * MIB)).to_le_bytes();
+        bytes[3 << 20..(3 << 20) + 8].copy_from_slice(&entry);
+        bytes[(3 << 20) + 16..(3 << 20) + 24].copy_from_slice(&entry);
+        bytes[(3 << 20) + 24..(3 << 20) + 32].copy_from_slice(&2u64.to_le_bytes());
+        let image = open_bytes(&bytes).unwrap();
+
+        assert_eq!(image.bat().payload_blocks()[3].state, PayloadState::Zero);
+        assert_eq!(
+            image.allocated_ranges().unwrap(),
+            [0..MIB, 2 * MIB..3 * MIB]
+        );
+    }
+
+    #[test]
+    fn bat_region_too_small() {
+        let mut metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        metadata.virtual_disk_size = 256 << 30;
+        let error = open_bytes(&vhdx_bytes(&metadata)).unwrap_err();
+        assert_eq!(error.parse_error().unwrap().field(), "BAT region length");
+    }
+
+    #[test]
+    fn replay_log_in_memory() {
+        let bytes = vhdx_with_log();
+        let file = TempFile::with_contents("log.vhdx", &bytes);
+        let image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+
+        assert!(image.log_replayed());
+        assert_eq!(image.allocated_ranges().unwrap(), vec![0..MIB]);
+        assert_eq!(std::fs::read(file.path()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn replay_log_to_disk() {
+        let file = TempFile::with_contents("log.vhdx", &vhdx_with_log());
+        let image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        assert!(image.log_replayed());
+        assert_eq!(image.header().sequence_number, 2);
+        assert!(image.header().log_guid.is_nil());
+        let file_write_guid = image.header().file_write_guid;
+        assert_ne!(file_write_guid, vhdx_header(1).file_write_guid);
+        assert_eq!(image.header_offset(), HEADER_OFFSETS[0]);
+        assert_eq!(image.physical_size().unwrap(), 5 * MIB);
+        drop(image);
+
+        let image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert!(!image.log_replayed());
+        assert_eq!(image.header().sequence_number, 2);
+        assert_eq!(image.header().file_write_guid, file_write_guid);
+        assert_eq!(image.allocated_ranges().unwrap(), vec![0..MIB]);
+    }
+
+    #[test]
+    fn log_beyond_end_of_file() {
+        let mut bytes = vhdx_with_log();
+        let header = VhdxHeader {
+            log_length: 64 << 20,
+            log_guid: Uuid::from_u128(0x10),
+            ..vhdx_header(1)
+        };
+        set_header(&mut bytes, 1, Some(header));
+        let error = open_bytes(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.structure(), Structure::VhdxHeader);
+        assert_eq!(parse_error.field(), "log length");
+        assert_eq!(parse_error.offset(), HEADER_OFFSETS[1] + 68);
+    }
+
+    #[test]
+    fn log_without_active_sequence() {
+        let mut bytes = vhdx_with_log();
+        bytes[(1 << 20) + 100] ^= 1;
+        let error = open_bytes(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        assert_eq!(
+            error.parse_error().unwrap().structure(),
+            Structure::LogEntry
+        );
+    }
+
+    /// Offset of the Page 83 Data item in the files of [`vhdx_bytes`], the fifth item after the
+    /// table, 4 KiB apart.
+    const PAGE_83_OFFSET: u64 = (2 << 20) + (80 << 10);
+
+    #[test]
+    fn write_metadata() {
+        let file = vhdx(4 * MIB, 1 << 20);
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        let id = Uuid::from_u128(0x83);
+        image
+            .write_metadata(PAGE_83_OFFSET, &id.to_bytes_le())
+            .unwrap();
+        assert_eq!(image.metadata().page_83_data, id);
+        assert!(!image.header().log_guid.is_nil());
+        assert_eq!(image.header().sequence_number, 2);
+        drop(image);
+
+        let image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert!(!image.log_replayed());
+        assert!(image.header().log_guid.is_nil());
+        assert_eq!(image.header().sequence_number, 3);
+        assert_eq!(image.metadata().page_83_data, id);
+    }
+
+    #[test]
+    fn crash_before_applying_log_entry() {
+        let file = vhdx(4 * MIB, 1 << 20);
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        let id = Uuid::from_u128(0x83);
+        let mut sector = vec![0; LOG_SECTOR_SIZE];
+        image.storage.read_at(PAGE_83_OFFSET, &mut sector).unwrap();
+        sector[..16].copy_from_slice(&id.to_bytes_le());
+        image.open_log().unwrap();
+        let writes = vec![LogWrite::Data {
+            file_offset: PAGE_83_OFFSET,
+            data: sector.into(),
+        }];
+        let file_handle = &image.storage.file;
+        image
+            .log
+            .as_mut()
+            .unwrap()
+            .write_entry(file_handle, writes)
+            .unwrap();
+        // the process dies: the entry is neither applied nor is the log emptied
+        std::mem::forget(image);
+
+        let image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert!(image.log_replayed());
+        assert_eq!(image.metadata().page_83_data, id);
+        drop(image);
+
+        let image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        assert!(image.log_replayed());
+        assert!(image.header().log_guid.is_nil());
+        assert_eq!(image.metadata().page_83_data, id);
+    }
+
+    #[test]
+    fn write_metadata_rejections() {
+        let file = vhdx(4 * MIB, 1 << 20);
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        let error = image.write_metadata(PAGE_83_OFFSET, &[0]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AccessDenied);
+        drop(image);
+
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        for offset in [HEADER_OFFSETS[1], MIB + 4096, MIB - 2, u64::MAX - 2] {
+            let error = image.write_metadata(offset, &[0; 4]).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+        // no update was made, so the header is untouched
+        assert!(image.header().log_guid.is_nil());
+    }
+
+    #[test]
+    fn read_blocks() {
+        let block_size = 1 << 20;
+        let first: Vec<u8> = (0..block_size).map(|i| (i / 512) as u8).collect();
+        let metadata = vhdx_metadata(3 * MIB + 4096, block_size as u32);
+        let mut bytes = vhdx_with_blocks(&metadata, &[(1, first.clone()), (3, vec![7; 4096])]);
+        // block 2 is zero, with a file offset it must not be read from
+        let entry = (3 << 20) + 16;
+        bytes[entry..entry + 8].copy_from_slice(&(2 | (4 * MIB)).to_le_bytes());
+        let mut image = open_bytes(&bytes).unwrap();
+
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents.len() as u64, 3 * MIB + 4096);
+        assert!(contents[..MIB as usize].iter().all(|&byte| byte == 0));
+        assert_eq!(&contents[MIB as usize..2 * MIB as usize], &first[..]);
+        assert!(contents[2 * MIB as usize..3 * MIB as usize]
+            .iter()
+            .all(|&byte| byte == 0));
+        assert!(contents[3 * MIB as usize..].iter().all(|&byte| byte == 7));
+
+        // a read across the boundary of blocks 0 and 1
+        let mut buf = [0xff; 1024];
+        image.seek(SeekFrom::Start(MIB - 512)).unwrap();
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..512], [0; 512]);
+        assert_eq!(buf[512..], first[..512]);
+
+        assert_eq!(image.seek(SeekFrom::End(0)).unwrap(), 3 * MIB + 4096);
+        assert_eq!(image.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_with_4k_sectors() {
+        let mut metadata = vhdx_metadata(64 * MIB, 32 << 20);
+        metadata.logical_sector_size = 4096;
+        metadata.physical_sector_size = 4096;
+        let data = vec![0x5a; 8192];
+        let mut image = open_bytes(&vhdx_with_blocks(&metadata, &[(1, data)])).unwrap();
+        assert_eq!(image.bat().chunk_ratio(), 1024);
+
+        let mut buf = vec![0; 8192];
+        image.seek(SeekFrom::Start(32 * MIB)).unwrap();
+        image.read_exact(&mut buf).unwrap();
+        assert!(buf.iter().all(|&byte| byte == 0x5a));
+    }
+
+    #[test]
+    fn read_through_replayed_log() {
+        let bytes = vhdx_with_log();
+        let mut image = open_bytes(&bytes).unwrap();
+        // the block the log allocates lies beyond the end of the file
+        let mut buf = [0xff; 16];
+        image.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0; 16]);
+    }
+
+    #[test]
+    fn write_allocates_blocks() {
+        let metadata = vhdx_metadata(3 * MIB + 4096, 1 << 20);
+        let mut bytes = vhdx_with_blocks(&metadata, &[(0, vec![1; 4096])]);
+        // the end of the file is not 1 MiB aligned
+        bytes.extend_from_slice(&[0xee; 100]);
+        let file = TempFile::with_contents("write.vhdx", &bytes);
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        let header = image.header().clone();
+
+        image.seek(SeekFrom::Start(MIB - 512)).unwrap();
+        image.write_all(&[2; 1024]).unwrap();
+        assert_eq!(image.bat().payload_blocks()[0].file_offset, 4 * MIB);
+        let block = image.bat().payload_blocks()[1];
+        assert_eq!(block.state, PayloadState::FullyPresent);
+        assert_eq!(block.file_offset, 6 * MIB);
+        assert_eq!(image.physical_size().unwrap(), 7 * MIB);
+
+        // the rest of the last block is beyond the virtual disk
+        image.seek(SeekFrom::Start(3 * MIB)).unwrap();
+        assert_eq!(image.write(&[3; 8192]).unwrap(), 4096);
+        assert_eq!(image.write(&[3; 16]).unwrap(), 0);
+        assert_eq!(image.bat().payload_blocks()[3].file_offset, 7 * MIB);
+        image.flush().unwrap();
+
+        let written = image.header().clone();
+        assert_ne!(written.file_write_guid, header.file_write_guid);
+        assert_ne!(written.data_write_guid, header.data_write_guid);
+        assert!(!written.log_guid.is_nil());
+        drop(image);
+
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        assert!(!image.log_replayed());
+        assert_eq!(image.header().file_write_guid, written.file_write_guid);
+        assert_eq!(image.header().data_write_guid, written.data_write_guid);
+        let mut contents = Vec::new();
+        image.read_to_end(&mut contents).unwrap();
+        let mb = MIB as usize;
+        assert!(contents[..4096].iter().all(|&byte| byte == 1));
+        assert!(contents[4096..mb - 512].iter().all(|&byte| byte == 0));
+        assert!(contents[mb - 512..mb + 512].iter().all(|&byte| byte == 2));
+        assert!(contents[mb + 512..3 * mb].iter().all(|&byte| byte == 0));
+        assert!(contents[3 * mb..].iter().all(|&byte| byte == 3));
+    }
+
+    #[test]
+    fn write_guids() {
+        let file = vhdx(4 * MIB, 1 << 20);
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        let header = image.header().clone();
+
+        image
+            .write_metadata(PAGE_83_OFFSET, &Uuid::from_u128(0x83).to_bytes_le())
+            .unwrap();
+        let file_write_guid = image.header().file_write_guid;
+        assert_ne!(file_write_guid, header.file_write_guid);
+        assert_eq!(image.header().data_write_guid, header.data_write_guid);
+
+        image.write_all(&[1; 512]).unwrap();
+        assert_eq!(image.header().file_write_guid, file_write_guid);
+        let data_write_guid = image.header().data_write_guid;
+        assert_ne!(data_write_guid, header.data_write_guid);
+
+        // a block that is already allocated is written in place
+        let sequence_number = image.header().sequence_number;
+        image.write_all(&[2; 512]).unwrap();
+        assert_eq!(image.header().sequence_number, sequence_number);
+        assert_eq!(image.header().data_write_guid, data_write_guid);
+    }
+
+    #[test]
+    fn write_rejections() {
+        let file = vhdx(4 * MIB, 1 << 20);
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadOnly).unwrap();
+        let error = image.write(&[0; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!image.bat().payload_blocks()[0].state.is_allocated());
+
+        let mut metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        metadata.file_parameters.has_parent = true;
+        metadata.parent_locator = Some(VhdxParentLocator {
+            locator_type: Uuid::from_u128(0xb04a_efb7_d19e_4a81_b789_25b8_e944_5913),
+            entries: Vec::new(),
+        });
+        let file = TempFile::with_contents("differencing.vhdx", &vhdx_bytes(&metadata));
+        let mut image = VhdxImage::open(file.path(), OpenMode::ReadWrite).unwrap();
+        let error = image.write(&[0; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(image.header().sequence_number, 1);
+    }
+
+    #[test]
+    fn differencing_cannot_be_read() {
+        let mut metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        metadata.file_parameters.has_parent = true;
+        metadata.parent_locator = Some(VhdxParentLocator {
+            locator_type: Uuid::from_u128(0xb04a_efb7_d19e_4a81_b789_25b8_e944_5913),
+            entries: Vec::new(),
+        });
+        let mut image = open_bytes(&vhdx_bytes(&metadata)).unwrap();
+        let error = image.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn differencing_metadata() {
+        let mut metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        metadata.file_parameters = FileParameters {
+            has_parent: true,
+            ..metadata.file_parameters
+        };
+        metadata.parent_locator = Some(VhdxParentLocator {
+            locator_type: Uuid::from_u128(0xb04a_efb7_d19e_4a81_b789_25b8_e944_5913),
+            entries: vec![
+                (
+                    "parent_linkage".into(),
+                    "{00000000-0000-0000-0000-000000000006}".into(),
+                ),
+                ("relative_path".into(), ".\\base.vhdx".into()),
+            ],
+        });
+        let image = open_bytes(&vhdx_bytes(&metadata)).unwrap();
+        let locator = image.metadata().parent_locator.as_ref().unwrap();
+        assert_eq!(locator.get("relative_path"), Some(".\\base.vhdx"));
+        assert_eq!(image.metadata(), &metadata);
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        let mut metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        metadata.logical_sector_size = 1024;
+        let error = open_bytes(&vhdx_bytes(&metadata)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        let parse_error = error.parse_error().unwrap();
+        assert_eq!(parse_error.structure(), Structure::MetadataItem);
+        assert_eq!(parse_error.field(), "logical sector size");
+
+        let mut metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        metadata.file_parameters.block_size = 3 << 20;
+        let error = open_bytes(&vhdx_bytes(&metadata)).unwrap_err();
+        assert_eq!(error.parse_error().unwrap().field(), "block size");
+
+        let mut metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        metadata.file_parameters.has_parent = true;
+        let error = open_bytes(&vhdx_bytes(&metadata)).unwrap_err();
+        assert_eq!(
+            error.parse_error().unwrap().structure(),
+            Structure::MetadataTable
+        );
+    }
+}
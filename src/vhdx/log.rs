@@ -0,0 +1,654 @@
+use std::fs::File;
+use std::io;
+
+use uuid::Uuid;
+
+use super::crc32c::checksum;
+use super::{guid, le_u32, le_u64};
+use crate::vhd::{read_exact_at, write_all_at};
+use crate::{Error, ParseError, Result, Structure};
+
+/// Size of the sectors the log is made of.
+pub(crate) const LOG_SECTOR_SIZE: usize = 4096;
+
+const ENTRY_SIGNATURE: &[u8; 4] = b"loge";
+const DATA_DESCRIPTOR_SIGNATURE: &[u8; 4] = b"desc";
+const ZERO_DESCRIPTOR_SIGNATURE: &[u8; 4] = b"zero";
+const DATA_SECTOR_SIGNATURE: &[u8; 4] = b"data";
+
+const CHECKSUM: usize = 4;
+const ENTRY_LENGTH: usize = 8;
+const TAIL: usize = 12;
+const SEQUENCE_NUMBER: usize = 16;
+const DESCRIPTOR_COUNT: usize = 24;
+const LOG_GUID: usize = 32;
+const FLUSHED_FILE_OFFSET: usize = 48;
+const LAST_FILE_OFFSET: usize = 56;
+const ENTRY_HEADER_SIZE: usize = 64;
+const DESCRIPTOR_SIZE: usize = 32;
+
+/// A write recorded in the log: 4 KiB of data or a run of zeros at an offset of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LogWrite {
+    Data { file_offset: u64, data: Box<[u8]> },
+    Zero { file_offset: u64, length: u64 },
+}
+
+impl LogWrite {
+    fn file_offset(&self) -> u64 {
+        match *self {
+            LogWrite::Data { file_offset, .. } | LogWrite::Zero { file_offset, .. } => file_offset,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        match self {
+            LogWrite::Data { data, .. } => data.len() as u64,
+            LogWrite::Zero { length, .. } => *length,
+        }
+    }
+}
+
+/// A valid log entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LogEntry {
+    pub(crate) sequence_number: u64,
+    /// Offset within the log of the oldest entry that must still be replayed.
+    pub(crate) tail: u64,
+    pub(crate) length: u64,
+    /// Size the file had at least when the entry was written.
+    pub(crate) flushed_file_offset: u64,
+    /// Size the file must have once the entry is replayed.
+    pub(crate) last_file_offset: u64,
+    pub(crate) writes: Vec<LogWrite>,
+}
+
+/// The writes of the active log sequence, to be replayed in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Replay {
+    pub(crate) writes: Vec<LogWrite>,
+    /// Size the file must have at least for the log to be replayable.
+    pub(crate) flushed_file_offset: u64,
+    /// Size of the file once the log is replayed.
+    pub(crate) last_file_offset: u64,
+}
+
+impl Replay {
+    /// Finds the active sequence of the log of `log_length` bytes at `log_offset` of `file`,
+    /// whose entries carry `log_guid`.
+    ///
+    /// The active sequence is the one with the highest sequence number whose entries have
+    /// consecutive sequence numbers and whose last entry has its tail at the first entry.
+    ///
+    /// # Errors
+    /// If the log cannot be read, or has no active sequence ([`ErrorKind::Corrupt`]).
+    ///
+    /// [`ErrorKind::Corrupt`]: crate::ErrorKind::Corrupt
+    pub(crate) fn find(
+        file: &File,
+        log_offset: u64,
+        log_length: u32,
+        log_guid: Uuid,
+    ) -> Result<Self> {
+        let mut log = vec![0; log_length as usize];
+        read_exact_at(file, log_offset, &mut log).map_err(|e| Error::io("open", e))?;
+
+        let mut active: Option<Vec<LogEntry>> = None;
+        for start in (0..log.len()).step_by(LOG_SECTOR_SIZE) {
+            let Some(first) = parse_entry(&log, start, log_guid) else {
+                continue;
+            };
+
+            let mut position = start;
+            let mut sequence = vec![first];
+            loop {
+                let last = sequence.last().unwrap();
+                position = (position + last.length as usize) % log.len();
+                if position == start {
+                    break;
+                }
+                match parse_entry(&log, position, log_guid) {
+                    Some(entry)
+                        if last.sequence_number.checked_add(1) == Some(entry.sequence_number) =>
+                    {
+                        sequence.push(entry);
+                    }
+                    _ => break,
+                }
+            }
+
+            let head = sequence.last().unwrap();
+            let is_complete = head.tail == start as u64;
+            let is_newer = active
+                .as_ref()
+                .is_none_or(|active| head.sequence_number > active.last().unwrap().sequence_number);
+            if is_complete && is_newer {
+                active = Some(sequence);
+            }
+        }
+
+        let Some(sequence) = active else {
+            return Err(Error::corrupt(
+                "open",
+                ParseError::new(Structure::LogEntry, "sequence", log_offset)
+                    .with_expected(format!("a complete log sequence with log GUID {log_guid}")),
+            ));
+        };
+
+        let head = sequence.last().unwrap();
+        Ok(Replay {
+            flushed_file_offset: head.flushed_file_offset,
+            last_file_offset: head.last_file_offset,
+            writes: sequence
+                .into_iter()
+                .flat_map(|entry| entry.writes)
+                .collect(),
+        })
+    }
+
+    /// Applies the writes to `file`, extends it to the size the log records and flushes it.
+    pub(crate) fn apply(&self, file: &File) -> io::Result<()> {
+        apply_writes(file, &self.writes)?;
+        if file.metadata()?.len() < self.last_file_offset {
+            file.set_len(self.last_file_offset)?;
+        }
+        file.sync_all()
+    }
+
+    /// Returns the size of the file, of `file_size` bytes, once the log is replayed.
+    pub(crate) fn file_size(&self, file_size: u64) -> u64 {
+        self.writes
+            .iter()
+            .map(|write| write.file_offset() + write.len())
+            .chain([file_size, self.last_file_offset])
+            .max()
+            .unwrap()
+    }
+
+    /// Reads `buf` at `offset` of `file` as if the writes had been applied. Bytes beyond the end
+    /// of the file, up to [`Replay::file_size`], read as zeros.
+    pub(crate) fn read_at(&self, file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let file_size = file.metadata()?.len();
+        let end = offset + buf.len() as u64;
+        if end > self.file_size(file_size) {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let in_file = file_size.saturating_sub(offset).min(buf.len() as u64) as usize;
+        read_exact_at(file, offset, &mut buf[..in_file])?;
+        buf[in_file..].fill(0);
+
+        for write in &self.writes {
+            let start = write.file_offset().max(offset);
+            let stop = (write.file_offset() + write.len()).min(end);
+            if start >= stop {
+                continue;
+            }
+            let target = &mut buf[(start - offset) as usize..(stop - offset) as usize];
+            match write {
+                LogWrite::Data { file_offset, data } => target.copy_from_slice(
+                    &data[(start - file_offset) as usize..(stop - file_offset) as usize],
+                ),
+                LogWrite::Zero { .. } => target.fill(0),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the entries of the log of a file opened for writing.
+///
+/// Every entry is its own sequence: it is only written once the previous one was applied and
+/// flushed, so its tail is the entry itself.
+#[derive(Debug)]
+pub(crate) struct LogWriter {
+    log_offset: u64,
+    log_length: u64,
+    log_guid: Uuid,
+    /// Offset within the log of the next entry.
+    head: u64,
+    sequence_number: u64,
+}
+
+impl LogWriter {
+    /// Creates a writer for the empty log of `log_length` bytes at `log_offset`, identified by
+    /// `log_guid`.
+    pub(crate) fn new(log_offset: u64, log_length: u32, log_guid: Uuid) -> Self {
+        LogWriter {
+            log_offset,
+            log_length: u64::from(log_length),
+            log_guid,
+            head: 0,
+            sequence_number: 1,
+        }
+    }
+
+    /// Flushes `file`, then writes and flushes an entry recording `writes`. The writes must be
+    /// applied afterwards, and flushed before the next entry is written.
+    ///
+    /// # Errors
+    /// If the file cannot be written or flushed, or the entry does not fit in the log.
+    pub(crate) fn write_entry(&mut self, file: &File, writes: Vec<LogWrite>) -> io::Result<()> {
+        file.sync_all()?;
+        let file_size = file.metadata()?.len();
+        let entry = LogEntry {
+            sequence_number: self.sequence_number,
+            tail: self.head,
+            length: 0,
+            flushed_file_offset: file_size,
+            last_file_offset: file_size,
+            writes,
+        };
+        let bytes = entry_bytes(&entry, self.log_guid);
+        let length = bytes.len() as u64;
+        if length > self.log_length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the update needs a log entry of {length} bytes but the log has {} bytes",
+                    self.log_length
+                ),
+            ));
+        }
+
+        let before_end = (self.log_length - self.head).min(length) as usize;
+        write_all_at(file, self.log_offset + self.head, &bytes[..before_end])?;
+        write_all_at(file, self.log_offset, &bytes[before_end..])?;
+        file.sync_data()?;
+
+        self.head = (self.head + length) % self.log_length;
+        self.sequence_number += 1;
+        Ok(())
+    }
+}
+
+/// Writes `writes` in place, in order.
+pub(crate) fn apply_writes(file: &File, writes: &[LogWrite]) -> io::Result<()> {
+    for write in writes {
+        match write {
+            LogWrite::Data { file_offset, data } => write_all_at(file, *file_offset, data)?,
+            LogWrite::Zero {
+                file_offset,
+                length,
+            } => {
+                let zeros = vec![0; LOG_SECTOR_SIZE * 16];
+                let mut offset = *file_offset;
+                let end = file_offset + length;
+                while offset < end {
+                    let len = (end - offset).min(zeros.len() as u64) as usize;
+                    write_all_at(file, offset, &zeros[..len])?;
+                    offset += len as u64;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the bytes of the log `log` from `start`, wrapping around at its end.
+fn wrapped(log: &[u8], start: usize, len: usize) -> Vec<u8> {
+    log.iter().cycle().skip(start).take(len).copied().collect()
+}
+
+/// Parses the entry at `start` of `log`. Returns `None` if it is not a valid entry of the log
+/// identified by `log_guid`.
+fn parse_entry(log: &[u8], start: usize, log_guid: Uuid) -> Option<LogEntry> {
+    let header = wrapped(log, start, ENTRY_HEADER_SIZE);
+    if &header[..4] != ENTRY_SIGNATURE || guid(&header, LOG_GUID) != log_guid {
+        return None;
+    }
+
+    let length = le_u32(&header, ENTRY_LENGTH) as usize;
+    let tail = le_u32(&header, TAIL) as usize;
+    let descriptor_count = le_u32(&header, DESCRIPTOR_COUNT) as usize;
+    if length == 0
+        || !length.is_multiple_of(LOG_SECTOR_SIZE)
+        || length > log.len()
+        || !tail.is_multiple_of(LOG_SECTOR_SIZE)
+        || tail >= log.len()
+        || ENTRY_HEADER_SIZE + descriptor_count * DESCRIPTOR_SIZE > length
+    {
+        return None;
+    }
+
+    let bytes = wrapped(log, start, length);
+    if le_u32(&bytes, CHECKSUM) != checksum(&bytes, CHECKSUM) {
+        return None;
+    }
+
+    // the specification reserves sequence number 0
+    let sequence_number = le_u64(&bytes, SEQUENCE_NUMBER);
+    if sequence_number == 0 {
+        return None;
+    }
+    let descriptors_size = ENTRY_HEADER_SIZE + descriptor_count * DESCRIPTOR_SIZE;
+    let mut data_sector = descriptors_size.next_multiple_of(LOG_SECTOR_SIZE);
+    let mut writes = Vec::with_capacity(descriptor_count);
+    for index in 0..descriptor_count {
+        let descriptor = &bytes[ENTRY_HEADER_SIZE + index * DESCRIPTOR_SIZE..][..DESCRIPTOR_SIZE];
+        let file_offset = le_u64(descriptor, 16);
+        if le_u64(descriptor, 24) != sequence_number
+            || !file_offset.is_multiple_of(LOG_SECTOR_SIZE as u64)
+        {
+            return None;
+        }
+
+        if &descriptor[..4] == ZERO_DESCRIPTOR_SIGNATURE {
+            let length = le_u64(descriptor, 8);
+            if !length.is_multiple_of(LOG_SECTOR_SIZE as u64) {
+                return None;
+            }
+            writes.push(LogWrite::Zero {
+                file_offset,
+                length,
+            });
+        } else if &descriptor[..4] == DATA_DESCRIPTOR_SIGNATURE {
+            let sector = bytes.get(data_sector..data_sector + LOG_SECTOR_SIZE)?;
+            let sequence_high = u64::from(le_u32(sector, 4));
+            let sequence_low = u64::from(le_u32(sector, LOG_SECTOR_SIZE - 4));
+            if &sector[..4] != DATA_SECTOR_SIGNATURE
+                || ((sequence_high << 32) | sequence_low) != sequence_number
+            {
+                return None;
+            }
+
+            let mut data = sector.to_vec();
+            data[..8].copy_from_slice(&descriptor[8..16]);
+            data[LOG_SECTOR_SIZE - 4..].copy_from_slice(&descriptor[4..8]);
+            writes.push(LogWrite::Data {
+                file_offset,
+                data: data.into_boxed_slice(),
+            });
+            data_sector += LOG_SECTOR_SIZE;
+        } else {
+            return None;
+        }
+    }
+
+    Some(LogEntry {
+        sequence_number,
+        tail: tail as u64,
+        length: length as u64,
+        flushed_file_offset: le_u64(&bytes, FLUSHED_FILE_OFFSET),
+        last_file_offset: le_u64(&bytes, LAST_FILE_OFFSET),
+        writes,
+    })
+}
+
+/// Serializes a log entry of the log identified by `log_guid`. The entry is padded to
+/// `entry.length` if it is shorter.
+pub(crate) fn entry_bytes(entry: &LogEntry, log_guid: Uuid) -> Vec<u8> {
+    let descriptors_size = ENTRY_HEADER_SIZE + entry.writes.len() * DESCRIPTOR_SIZE;
+    let mut bytes = vec![0; descriptors_size.next_multiple_of(LOG_SECTOR_SIZE)];
+
+    for (index, write) in entry.writes.iter().enumerate() {
+        let descriptor = ENTRY_HEADER_SIZE + index * DESCRIPTOR_SIZE;
+        match write {
+            LogWrite::Zero {
+                file_offset,
+                length,
+            } => {
+                bytes[descriptor..descriptor + 4].copy_from_slice(ZERO_DESCRIPTOR_SIGNATURE);
+                bytes[descriptor + 8..descriptor + 16].copy_from_slice(&length.to_le_bytes());
+                bytes[descriptor + 16..descriptor + 24].copy_from_slice(&file_offset.to_le_bytes());
+            }
+            LogWrite::Data { file_offset, data } => {
+                bytes[descriptor..descriptor + 4].copy_from_slice(DATA_DESCRIPTOR_SIGNATURE);
+                bytes[descriptor + 4..descriptor + 8].copy_from_slice(&data[LOG_SECTOR_SIZE - 4..]);
+                bytes[descriptor + 8..descriptor + 16].copy_from_slice(&data[..8]);
+                bytes[descriptor + 16..descriptor + 24].copy_from_slice(&file_offset.to_le_bytes());
+
+                let mut sector = data.to_vec();
+                sector[..4].copy_from_slice(DATA_SECTOR_SIGNATURE);
+                sector[4..8].copy_from_slice(&((entry.sequence_number >> 32) as u32).to_le_bytes());
+                sector[LOG_SECTOR_SIZE - 4..]
+                    .copy_from_slice(&(entry.sequence_number as u32).to_le_bytes());
+                bytes.extend_from_slice(&sector);
+            }
+        }
+        bytes[descriptor + 24..descriptor + 32]
+            .copy_from_slice(&entry.sequence_number.to_le_bytes());
+    }
+
+    bytes.resize(bytes.len().max(entry.length as usize), 0);
+    bytes[..4].copy_from_slice(ENTRY_SIGNATURE);
+    let length = bytes.len() as u32;
+    bytes[ENTRY_LENGTH..ENTRY_LENGTH + 4].copy_from_slice(&length.to_le_bytes());
+    bytes[TAIL..TAIL + 4].copy_from_slice(&(entry.tail as u32).to_le_bytes());
+    bytes[SEQUENCE_NUMBER..SEQUENCE_NUMBER + 8]
+        .copy_from_slice(&entry.sequence_number.to_le_bytes());
+    bytes[DESCRIPTOR_COUNT..DESCRIPTOR_COUNT + 4]
+        .copy_from_slice(&(entry.writes.len() as u32).to_le_bytes());
+    bytes[LOG_GUID..LOG_GUID + 16].copy_from_slice(&log_guid.to_bytes_le());
+    bytes[FLUSHED_FILE_OFFSET..FLUSHED_FILE_OFFSET + 8]
+        .copy_from_slice(&entry.flushed_file_offset.to_le_bytes());
+    bytes[LAST_FILE_OFFSET..LAST_FILE_OFFSET + 8]
+        .copy_from_slice(&entry.last_file_offset.to_le_bytes());
+
+    let checksum = checksum(&bytes, CHECKSUM);
+    bytes[CHECKSUM..CHECKSUM + 4].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+    use crate::test_util::TempFile;
+    use crate::ErrorKind;
+
+    const LOG_GUID_VALUE: Uuid = Uuid::from_u128(0x1111);
+
+    fn data(byte: u8) -> Box<[u8]> {
+        let mut data = vec![byte; LOG_SECTOR_SIZE];
+        data[..8].copy_from_slice(b"leading!");
+        data[LOG_SECTOR_SIZE - 4..].copy_from_slice(b"tail");
+        data.into_boxed_slice()
+    }
+
+    fn entry(sequence_number: u64, tail: u64, writes: Vec<LogWrite>) -> LogEntry {
+        LogEntry {
+            sequence_number,
+            tail,
+            length: 0,
+            flushed_file_offset: 4096,
+            last_file_offset: 1 << 20,
+            writes,
+        }
+    }
+
+    /// Writes a 64 KiB log holding `entries` at the given offsets, after a 4 KiB prefix.
+    fn log_file(entries: &[(usize, &LogEntry)]) -> TempFile {
+        let mut log = vec![0; 64 << 10];
+        for &(offset, entry) in entries {
+            let bytes = entry_bytes(entry, LOG_GUID_VALUE);
+            for (i, byte) in bytes.into_iter().enumerate() {
+                log[(offset + i) % (64 << 10)] = byte;
+            }
+        }
+        let mut bytes = vec![0xaa; 4096];
+        bytes.extend_from_slice(&log);
+        TempFile::with_contents("log.vhdx", &bytes)
+    }
+
+    fn find(file: &TempFile) -> Result<Replay> {
+        let file = File::open(file.path()).unwrap();
+        Replay::find(&file, 4096, 64 << 10, LOG_GUID_VALUE)
+    }
+
+    #[test]
+    fn data_and_zero_descriptors() {
+        let writes = vec![
+            LogWrite::Data {
+                file_offset: 8192,
+                data: data(1),
+            },
+            LogWrite::Zero {
+                file_offset: 0,
+                length: 4096,
+            },
+        ];
+        let entry = entry(3, 0, writes.clone());
+        let file = log_file(&[(0, &entry)]);
+
+        let replay = find(&file).unwrap();
+        assert_eq!(replay.writes, writes);
+        assert_eq!(replay.flushed_file_offset, 4096);
+        assert_eq!(replay.last_file_offset, 1 << 20);
+    }
+
+    #[test]
+    fn active_sequence() {
+        let first = entry(
+            5,
+            8192,
+            vec![LogWrite::Data {
+                file_offset: 0,
+                data: data(5),
+            }],
+        );
+        let second = entry(
+            6,
+            8192,
+            vec![LogWrite::Data {
+                file_offset: 0,
+                data: data(6),
+            }],
+        );
+        // an older sequence that was overwritten, and a newer entry of another log
+        let stale = entry(4, 0, Vec::new());
+        let mut other_log = entry_bytes(&entry(9, 0, Vec::new()), Uuid::from_u128(2));
+        other_log.resize(4096, 0);
+
+        let file = log_file(&[(0, &stale), (8192, &first), (16384, &second)]);
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        bytes[4096 + 32768..4096 + 36864].copy_from_slice(&other_log);
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let replay = find(&file).unwrap();
+        assert_eq!(replay.writes.len(), 2);
+        assert_eq!(replay.writes[1], second.writes[0]);
+    }
+
+    #[test]
+    fn wrapped_entry() {
+        let entry = entry(
+            7,
+            (60 << 10) as u64,
+            vec![LogWrite::Data {
+                file_offset: 4096,
+                data: data(7),
+            }],
+        );
+        let file = log_file(&[(60 << 10, &entry)]);
+        assert_eq!(find(&file).unwrap().writes, entry.writes);
+    }
+
+    #[test]
+    fn incomplete_sequence() {
+        // the head points to a tail entry that was overwritten
+        let head = entry(6, 0, Vec::new());
+        let file = log_file(&[(4096, &head)]);
+        let error = find(&file).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Corrupt);
+        assert_eq!(
+            error.parse_error().unwrap().structure(),
+            Structure::LogEntry
+        );
+
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        bytes[4096 + 4096 + 100] ^= 1;
+        std::fs::write(file.path(), &bytes).unwrap();
+        assert!(find(&file).is_err());
+    }
+
+    #[test]
+    fn sequence_number_bounds() {
+        // the sequence number following the last one cannot be represented
+        let last = entry(u64::MAX, 0, Vec::new());
+        let next = entry(1, 4096, Vec::new());
+        let file = log_file(&[(0, &last), (4096, &next)]);
+        let replay = find(&file).unwrap();
+        assert!(replay.writes.is_empty());
+
+        let reserved = entry(0, 0, Vec::new());
+        let file = log_file(&[(0, &reserved)]);
+        assert_eq!(find(&file).unwrap_err().kind(), ErrorKind::Corrupt);
+    }
+
+    #[test]
+    fn read_through_replay() {
+        let file = TempFile::with_contents("replay.vhdx", &[0xff; 8192]);
+        let replay = Replay {
+            writes: vec![
+                LogWrite::Data {
+                    file_offset: 4096,
+                    data: data(1),
+                },
+                LogWrite::Zero {
+                    file_offset: 6144,
+                    length: 4096,
+                },
+            ],
+            flushed_file_offset: 8192,
+            last_file_offset: 12288,
+        };
+        let file = File::open(file.path()).unwrap();
+        assert_eq!(replay.file_size(8192), 12288);
+
+        let mut buf = vec![0; 12288];
+        replay.read_at(&file, 0, &mut buf).unwrap();
+        assert_eq!(
+            &buf[4088..4104],
+            b"\xff\xff\xff\xff\xff\xff\xff\xffleading!"
+        );
+        assert!(buf[4104..6144].iter().all(|&byte| byte == 1));
+        assert!(buf[6144..].iter().all(|&byte| byte == 0));
+
+        let mut buf = [0; 4];
+        assert!(replay.read_at(&file, 12286, &mut buf).is_err());
+    }
+
+    #[test]
+    fn writer_wraps_around() {
+        let file = TempFile::with_contents("log.vhdx", &vec![0; 4096 + (64 << 10)]);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(file.path())
+            .unwrap();
+        let mut writer = LogWriter::new(4096, 64 << 10, LOG_GUID_VALUE);
+
+        // a 4 KiB entry and 7 entries of 8 KiB fill 60 KiB of the log, the next one wraps around
+        let zero = vec![LogWrite::Zero {
+            file_offset: 0,
+            length: 4096,
+        }];
+        writer.write_entry(&file, zero).unwrap();
+        for sequence in 1..=8u8 {
+            let writes = vec![LogWrite::Data {
+                file_offset: 4096,
+                data: data(sequence),
+            }];
+            writer.write_entry(&file, writes.clone()).unwrap();
+
+            let replay = Replay::find(&file, 4096, 64 << 10, LOG_GUID_VALUE).unwrap();
+            assert_eq!(replay.writes, writes);
+        }
+        assert_eq!(writer.head, 4096);
+
+        let error = writer
+            .write_entry(
+                &file,
+                (0..16)
+                    .map(|_| LogWrite::Data {
+                        file_offset: 0,
+                        data: data(0),
+                    })
+                    .collect(),
+            )
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,512 @@
+use uuid::Uuid;
+
+use super::header::MIB;
+use super::{guid, le_u16, le_u32, le_u64};
+use crate::{ParseError, Structure};
+
+/// Size of the metadata table at the start of the metadata region.
+pub(crate) const METADATA_TABLE_SIZE: usize = 64 << 10;
+
+pub(crate) const FILE_PARAMETERS: Uuid = Uuid::from_u128(0xcaa1_6737_fa36_4d43_b3b6_33f0_aa44_e76b);
+pub(crate) const VIRTUAL_DISK_SIZE: Uuid =
+    Uuid::from_u128(0x2fa5_4224_cd1b_4876_b211_5dbe_d83b_f4b8);
+pub(crate) const PAGE_83_DATA: Uuid = Uuid::from_u128(0xbeca_12ab_b2e6_4523_93ef_c309_e000_c746);
+pub(crate) const LOGICAL_SECTOR_SIZE: Uuid =
+    Uuid::from_u128(0x8141_bf1d_a96f_4709_ba47_f233_a8fa_ab5f);
+pub(crate) const PHYSICAL_SECTOR_SIZE: Uuid =
+    Uuid::from_u128(0xcda3_48c7_445d_4471_9cc9_e988_5251_c556);
+pub(crate) const PARENT_LOCATOR: Uuid = Uuid::from_u128(0xa8d3_5f2d_b30b_454d_abf7_d3d8_4834_ab0c);
+/// The metadata items defined by the specification.
+pub(crate) const KNOWN_ITEMS: [Uuid; 6] = [
+    FILE_PARAMETERS,
+    VIRTUAL_DISK_SIZE,
+    PAGE_83_DATA,
+    LOGICAL_SECTOR_SIZE,
+    PHYSICAL_SECTOR_SIZE,
+    PARENT_LOCATOR,
+];
+
+const SIGNATURE: &[u8; 8] = b"metadata";
+const MAX_ENTRIES: u16 = 2047;
+/// Largest virtual disk the specification allows, 64 TiB.
+const MAX_VIRTUAL_DISK_SIZE: u64 = 64 << 40;
+
+const ENTRY_COUNT: usize = 10;
+const ENTRIES: usize = 32;
+const ENTRY_SIZE: usize = 32;
+const LOCATOR_HEADER_SIZE: usize = 20;
+const LOCATOR_ENTRY_SIZE: usize = 12;
+
+/// An entry of the metadata table, locating a metadata item within the metadata region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataTableEntry {
+    pub item_id: Uuid,
+    /// Offset of the item relative to the start of the metadata region, at least 64 KiB.
+    pub offset: u32,
+    /// Size of the item in bytes.
+    pub length: u32,
+    /// Whether the item is user metadata rather than system metadata.
+    pub is_user: bool,
+    /// Whether the item describes the virtual disk rather than the file, and must be copied to
+    /// another file representing the same disk.
+    pub is_virtual_disk: bool,
+    /// Whether the file cannot be opened by implementations that do not know the item.
+    pub required: bool,
+}
+
+/// The metadata table at the start of the metadata region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataTable {
+    pub entries: Vec<MetadataTableEntry>,
+}
+
+impl MetadataTable {
+    /// Parses the metadata table in `bytes`, which was read from file offset `offset`.
+    ///
+    /// # Errors
+    /// If the signature or entry count is invalid.
+    pub fn parse(bytes: &[u8; METADATA_TABLE_SIZE], offset: u64) -> Result<Self, ParseError> {
+        if &bytes[..8] != SIGNATURE {
+            return Err(ParseError::mismatch(
+                Structure::MetadataTable,
+                "signature",
+                offset,
+                "\"metadata\"",
+                format!("{:?}", String::from_utf8_lossy(&bytes[..8])),
+            ));
+        }
+
+        let entry_count = le_u16(bytes, ENTRY_COUNT);
+        if entry_count > MAX_ENTRIES {
+            return Err(ParseError::mismatch(
+                Structure::MetadataTable,
+                "entry count",
+                offset + ENTRY_COUNT as u64,
+                format!("at most {MAX_ENTRIES}"),
+                entry_count,
+            ));
+        }
+
+        let entries = (0..usize::from(entry_count))
+            .map(|index| {
+                let entry = ENTRIES + index * ENTRY_SIZE;
+                let flags = le_u32(bytes, entry + 24);
+                MetadataTableEntry {
+                    item_id: guid(bytes, entry),
+                    offset: le_u32(bytes, entry + 16),
+                    length: le_u32(bytes, entry + 20),
+                    is_user: flags & 1 != 0,
+                    is_virtual_disk: flags & 2 != 0,
+                    required: flags & 4 != 0,
+                }
+            })
+            .collect();
+
+        Ok(MetadataTable { entries })
+    }
+
+    /// Returns the entry of the item identified by `item_id`.
+    pub fn item(&self, item_id: Uuid) -> Option<&MetadataTableEntry> {
+        self.entries.iter().find(|entry| entry.item_id == item_id)
+    }
+
+    /// Serializes the metadata table.
+    pub fn to_bytes(&self) -> Box<[u8; METADATA_TABLE_SIZE]> {
+        let mut bytes = Box::new([0; METADATA_TABLE_SIZE]);
+        bytes[..8].copy_from_slice(SIGNATURE);
+        bytes[ENTRY_COUNT..ENTRY_COUNT + 2]
+            .copy_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        for (index, item) in self.entries.iter().enumerate() {
+            let entry = ENTRIES + index * ENTRY_SIZE;
+            let flags = u32::from(item.is_user)
+                | u32::from(item.is_virtual_disk) << 1
+                | u32::from(item.required) << 2;
+            bytes[entry..entry + 16].copy_from_slice(&item.item_id.to_bytes_le());
+            bytes[entry + 16..entry + 20].copy_from_slice(&item.offset.to_le_bytes());
+            bytes[entry + 20..entry + 24].copy_from_slice(&item.length.to_le_bytes());
+            bytes[entry + 24..entry + 28].copy_from_slice(&flags.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// The File Parameters metadata item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileParameters {
+    /// Size of a payload block in bytes, a power of two from 1 MiB to 256 MiB.
+    pub block_size: u32,
+    /// Whether blocks must stay allocated, as in a fixed VHDX.
+    pub leave_blocks_allocated: bool,
+    /// Whether the file is a differencing disk.
+    pub has_parent: bool,
+}
+
+/// The Parent Locator metadata item of a differencing VHDX: key-value pairs such as
+/// `parent_linkage`, `relative_path` and `absolute_win32_path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdxParentLocator {
+    /// Type of the locator, which defines the keys.
+    pub locator_type: Uuid,
+    /// The key-value pairs, in the order they are stored.
+    pub entries: Vec<(String, String)>,
+}
+
+impl VhdxParentLocator {
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The well-known items of the metadata region of a VHDX file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdxMetadata {
+    pub file_parameters: FileParameters,
+    /// Size of the virtual disk in bytes.
+    pub virtual_disk_size: u64,
+    /// Sector size the virtual disk reports, 512 or 4096 bytes.
+    pub logical_sector_size: u32,
+    /// Physical sector size the virtual disk reports, 512 or 4096 bytes.
+    pub physical_sector_size: u32,
+    /// The Page 83 Data item: the unique ID of the virtual disk.
+    pub page_83_data: Uuid,
+    /// Locates the parent of a differencing disk.
+    pub parent_locator: Option<VhdxParentLocator>,
+}
+
+impl VhdxMetadata {
+    /// Decodes the items listed in `table` from `region`, the metadata region read from file
+    /// offset `offset`.
+    ///
+    /// # Errors
+    /// If a required item is missing, lies outside of the region or has an invalid value.
+    pub fn parse(table: &MetadataTable, region: &[u8], offset: u64) -> Result<Self, ParseError> {
+        let items = Items {
+            table,
+            region,
+            offset,
+        };
+
+        let (bytes, item_offset) = items.required(FILE_PARAMETERS, "File Parameters", 8)?;
+        let block_size = le_u32(bytes, 0);
+        if !block_size.is_power_of_two() || !(MIB..=256 * MIB).contains(&u64::from(block_size)) {
+            return Err(ParseError::mismatch(
+                Structure::MetadataItem,
+                "block size",
+                item_offset,
+                "a power of two from 1 MiB to 256 MiB",
+                block_size,
+            ));
+        }
+        let flags = le_u32(bytes, 4);
+        let file_parameters = FileParameters {
+            block_size,
+            leave_blocks_allocated: flags & 1 != 0,
+            has_parent: flags & 2 != 0,
+        };
+
+        let sector_size = |id, name, field| -> Result<u32, ParseError> {
+            let (bytes, item_offset) = items.required(id, name, 4)?;
+            match le_u32(bytes, 0) {
+                size @ (512 | 4096) => Ok(size),
+                size => Err(ParseError::mismatch(
+                    Structure::MetadataItem,
+                    field,
+                    item_offset,
+                    "512 or 4096",
+                    size,
+                )),
+            }
+        };
+        let logical_sector_size = sector_size(
+            LOGICAL_SECTOR_SIZE,
+            "Logical Sector Size",
+            "logical sector size",
+        )?;
+        let physical_sector_size = sector_size(
+            PHYSICAL_SECTOR_SIZE,
+            "Physical Sector Size",
+            "physical sector size",
+        )?;
+
+        let (bytes, item_offset) = items.required(VIRTUAL_DISK_SIZE, "Virtual Disk Size", 8)?;
+        let virtual_disk_size = le_u64(bytes, 0);
+        if virtual_disk_size > MAX_VIRTUAL_DISK_SIZE
+            || !virtual_disk_size.is_multiple_of(u64::from(logical_sector_size))
+        {
+            return Err(ParseError::mismatch(
+                Structure::MetadataItem,
+                "virtual disk size",
+                item_offset,
+                format!("a multiple of {logical_sector_size} of at most 64 TiB"),
+                virtual_disk_size,
+            ));
+        }
+
+        let (bytes, _) = items.required(PAGE_83_DATA, "Page 83 Data", 16)?;
+        let page_83_data = guid(bytes, 0);
+
+        let parent_locator = if file_parameters.has_parent {
+            let (bytes, item_offset) =
+                items.required(PARENT_LOCATOR, "Parent Locator", LOCATOR_HEADER_SIZE)?;
+            Some(parse_parent_locator(bytes, item_offset)?)
+        } else {
+            None
+        };
+
+        Ok(VhdxMetadata {
+            file_parameters,
+            virtual_disk_size,
+            logical_sector_size,
+            physical_sector_size,
+            page_83_data,
+            parent_locator,
+        })
+    }
+}
+
+/// The items of a metadata region.
+struct Items<'a> {
+    table: &'a MetadataTable,
+    region: &'a [u8],
+    /// File offset of the region.
+    offset: u64,
+}
+
+impl<'a> Items<'a> {
+    /// Returns the bytes and file offset of the item `id`, named `name`, which must be at least
+    /// `min_length` bytes long.
+    fn required(
+        &self,
+        id: Uuid,
+        name: &str,
+        min_length: usize,
+    ) -> Result<(&'a [u8], u64), ParseError> {
+        let index = self
+            .table
+            .entries
+            .iter()
+            .position(|entry| entry.item_id == id)
+            .ok_or_else(|| {
+                ParseError::new(Structure::MetadataTable, "entries", self.offset)
+                    .with_expected(format!("a {name} item"))
+            })?;
+        let entry = &self.table.entries[index];
+        let entry_offset = self.offset + (ENTRIES + index * ENTRY_SIZE) as u64;
+        let start = entry.offset as usize;
+        let end = start
+            .checked_add(entry.length as usize)
+            .filter(|&end| start >= METADATA_TABLE_SIZE && end <= self.region.len())
+            .ok_or_else(|| {
+                ParseError::mismatch(
+                    Structure::MetadataTable,
+                    "item offset",
+                    entry_offset + 16,
+                    format!(
+                        "an item within {METADATA_TABLE_SIZE:#x}..{:#x}",
+                        self.region.len()
+                    ),
+                    format!(
+                        "{start:#x}..{:#x}",
+                        u64::from(entry.offset) + u64::from(entry.length)
+                    ),
+                )
+            })?;
+        if (entry.length as usize) < min_length {
+            return Err(ParseError::mismatch(
+                Structure::MetadataTable,
+                "item length",
+                entry_offset + 20,
+                format!("at least {min_length}"),
+                entry.length,
+            ));
+        }
+
+        Ok((&self.region[start..end], self.offset + start as u64))
+    }
+}
+
+/// Parses the Parent Locator item in `bytes`, which was read from file offset `offset`.
+fn parse_parent_locator(bytes: &[u8], offset: u64) -> Result<VhdxParentLocator, ParseError> {
+    let locator_type = guid(bytes, 0);
+    let count = usize::from(le_u16(bytes, 18));
+
+    let string = |string_offset: u32, length: u16, field| {
+        let start = string_offset as usize;
+        let string = start
+            .checked_add(usize::from(length))
+            .and_then(|end| bytes.get(start..end));
+        match string {
+            Some(string) if length.is_multiple_of(2) => {
+                let units: Vec<u16> = string
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect();
+                Ok(String::from_utf16_lossy(&units))
+            }
+            _ => Err(
+                ParseError::new(Structure::MetadataItem, field, offset + start as u64)
+                    .with_found(format!("{length} bytes at {start:#x}")),
+            ),
+        }
+    };
+
+    let mut entries = Vec::with_capacity(count);
+    for index in 0..count {
+        let entry = LOCATOR_HEADER_SIZE + index * LOCATOR_ENTRY_SIZE;
+        if entry + LOCATOR_ENTRY_SIZE > bytes.len() {
+            return Err(ParseError::mismatch(
+                Structure::MetadataItem,
+                "key-value count",
+                offset + 18,
+                format!(
+                    "at most {}",
+                    (bytes.len() - LOCATOR_HEADER_SIZE) / LOCATOR_ENTRY_SIZE
+                ),
+                count,
+            ));
+        }
+        let key = string(le_u32(bytes, entry), le_u16(bytes, entry + 8), "key")?;
+        let value = string(le_u32(bytes, entry + 4), le_u16(bytes, entry + 10), "value")?;
+        entries.push((key, value));
+    }
+
+    Ok(VhdxParentLocator {
+        locator_type,
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{vhdx_metadata, vhdx_metadata_region};
+
+    /// File offset of the metadata region in the tests.
+    const OFFSET: u64 = 2 * MIB;
+
+    fn table(region: &[u8]) -> MetadataTable {
+        MetadataTable::parse(region[..METADATA_TABLE_SIZE].try_into().unwrap(), OFFSET).unwrap()
+    }
+
+    /// Returns a Parent Locator item with one key-value pair, `key` and `value` stored in UTF-16
+    /// after the entry.
+    fn locator_bytes(key: &str, value: &str) -> Vec<u8> {
+        let key: Vec<u8> = key.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let value: Vec<u8> = value.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let strings = (LOCATOR_HEADER_SIZE + LOCATOR_ENTRY_SIZE) as u32;
+        let mut bytes = vec![0; LOCATOR_HEADER_SIZE];
+        bytes[18..20].copy_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&strings.to_le_bytes());
+        bytes.extend_from_slice(&(strings + key.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&key);
+        bytes.extend_from_slice(&value);
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let metadata = vhdx_metadata(4 * MIB, 1 << 20);
+        let region = vhdx_metadata_region(&metadata);
+        let table = table(&region);
+        assert_eq!(table.entries.len(), 5);
+        assert!(table.item(PAGE_83_DATA).unwrap().is_virtual_disk);
+        assert_eq!(&table.to_bytes()[..], &region[..METADATA_TABLE_SIZE]);
+        assert_eq!(
+            VhdxMetadata::parse(&table, &region, OFFSET).unwrap(),
+            metadata
+        );
+    }
+
+    #[test]
+    fn invalid_table() {
+        let region = vhdx_metadata_region(&vhdx_metadata(4 * MIB, 1 << 20));
+        let mut bytes: Box<[u8; METADATA_TABLE_SIZE]> =
+            Box::new(region[..METADATA_TABLE_SIZE].try_into().unwrap());
+
+        bytes[0] = b'M';
+        let error = MetadataTable::parse(&bytes, OFFSET).unwrap_err();
+        assert_eq!(error.structure(), Structure::MetadataTable);
+        assert_eq!(error.field(), "signature");
+        assert_eq!(error.offset(), OFFSET);
+
+        bytes[0] = b'm';
+        bytes[ENTRY_COUNT..ENTRY_COUNT + 2].copy_from_slice(&2048u16.to_le_bytes());
+        let error = MetadataTable::parse(&bytes, OFFSET).unwrap_err();
+        assert_eq!(error.field(), "entry count");
+        assert_eq!(error.offset(), OFFSET + ENTRY_COUNT as u64);
+    }
+
+    #[test]
+    fn invalid_items() {
+        let region = vhdx_metadata_region(&vhdx_metadata(4 * MIB, 1 << 20));
+        let table = table(&region);
+        let index = table
+            .entries
+            .iter()
+            .position(|entry| entry.item_id == VIRTUAL_DISK_SIZE)
+            .unwrap();
+        let entry_offset = OFFSET + (ENTRIES + index * ENTRY_SIZE) as u64;
+        let parse = |change: &dyn Fn(&mut MetadataTableEntry)| {
+            let mut table = table.clone();
+            change(&mut table.entries[index]);
+            VhdxMetadata::parse(&table, &region, OFFSET).unwrap_err()
+        };
+
+        // inside the metadata table
+        let error = parse(&|entry| entry.offset = 4096);
+        assert_eq!(error.field(), "item offset");
+        assert_eq!(error.offset(), entry_offset + 16);
+        // past the end of the region
+        let error = parse(&|entry| entry.offset = MIB as u32 - 4);
+        assert_eq!(error.field(), "item offset");
+        let error = parse(&|entry| {
+            entry.offset = u32::MAX;
+            entry.length = u32::MAX;
+        });
+        assert_eq!(error.field(), "item offset");
+        let error = parse(&|entry| entry.length = 4);
+        assert_eq!(error.field(), "item length");
+        assert_eq!(error.offset(), entry_offset + 20);
+        let error = parse(&|entry| entry.item_id = Uuid::from_u128(0x1234));
+        assert_eq!(error.structure(), Structure::MetadataTable);
+        assert_eq!(error.field(), "entries");
+    }
+
+    #[test]
+    fn parent_locator() {
+        let bytes = locator_bytes("relative_path", ".\\base.vhdx");
+        let locator = parse_parent_locator(&bytes, OFFSET).unwrap();
+        assert_eq!(locator.get("relative_path"), Some(".\\base.vhdx"));
+        assert_eq!(locator.get("parent_linkage"), None);
+
+        // a second entry would overlap the end of the item
+        let mut overflow = locator_bytes("", "");
+        overflow[18..20].copy_from_slice(&2u16.to_le_bytes());
+        let error = parse_parent_locator(&overflow, OFFSET).unwrap_err();
+        assert_eq!(error.field(), "key-value count");
+        assert_eq!(error.offset(), OFFSET + 18);
+
+        let mut out_of_range = bytes.clone();
+        let entry = LOCATOR_HEADER_SIZE;
+        out_of_range[entry..entry + 4].copy_from_slice(&0x1000u32.to_le_bytes());
+        let error = parse_parent_locator(&out_of_range, OFFSET).unwrap_err();
+        assert_eq!(error.field(), "key");
+        assert_eq!(error.offset(), OFFSET + 0x1000);
+
+        out_of_range[entry..entry + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        out_of_range[entry + 8..entry + 10].copy_from_slice(&u16::MAX.to_le_bytes());
+        let error = parse_parent_locator(&out_of_range, OFFSET).unwrap_err();
+        assert_eq!(error.field(), "key");
+
+        let mut odd_length = bytes;
+        odd_length[entry + 10] -= 1;
+        let error = parse_parent_locator(&odd_length, OFFSET).unwrap_err();
+        assert_eq!(error.field(), "value");
+    }
+}
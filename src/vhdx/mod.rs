@@ -0,0 +1,37 @@
+//! Pure-Rust implementation of the VHDX file format (VHDX Format Specification, version 1.00).
+
+pub use bat::{PayloadBlock, PayloadState, SectorBitmapBlock, VhdxBat};
+pub use header::{FileIdentifier, VhdxHeader};
+pub use image::VhdxImage;
+pub use metadata::{
+    FileParameters, MetadataTable, MetadataTableEntry, VhdxMetadata, VhdxParentLocator,
+};
+pub use region::{RegionTable, RegionTableEntry};
+
+mod bat;
+mod crc32c;
+pub(crate) mod header;
+mod image;
+mod log;
+pub(crate) mod metadata;
+pub(crate) mod region;
+
+/// Reads a little-endian `u16` at `offset` of `bytes`.
+fn le_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+/// Reads a little-endian `u32` at `offset` of `bytes`.
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a little-endian `u64` at `offset` of `bytes`.
+fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Reads a GUID, stored in the layout Windows uses, at `offset` of `bytes`.
+fn guid(bytes: &[u8], offset: usize) -> uuid::Uuid {
+    uuid::Uuid::from_bytes_le(bytes[offset..offset + 16].try_into().unwrap())
+}
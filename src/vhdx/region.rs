@@ -0,0 +1,212 @@
+use uuid::Uuid;
+
+use super::crc32c::checksum;
+use super::header::MIB;
+use super::{guid, le_u32, le_u64};
+use crate::{ParseError, Structure};
+
+/// Size of a region table, over which its checksum is computed.
+pub(crate) const REGION_TABLE_SIZE: usize = 64 << 10;
+/// Offsets of the two copies of the region table.
+pub(crate) const REGION_TABLE_OFFSETS: [u64; 2] = [192 << 10, 256 << 10];
+/// GUID of the region of the Block Allocation Table.
+pub(crate) const BAT_REGION: Uuid = Uuid::from_u128(0x2dc2_7766_f623_4200_9d64_115e_9bfd_4a08);
+/// GUID of the metadata region.
+pub(crate) const METADATA_REGION: Uuid = Uuid::from_u128(0x8b7c_a206_4790_4b9a_b8fe_575f_050f_886e);
+
+const SIGNATURE: &[u8; 4] = b"regi";
+const MAX_ENTRIES: u32 = 2047;
+
+const CHECKSUM: usize = 4;
+const ENTRY_COUNT: usize = 8;
+const ENTRIES: usize = 16;
+const ENTRY_SIZE: usize = 32;
+
+/// An entry of the region table, locating a region of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionTableEntry {
+    /// Identifies the region, e.g. the BAT or the metadata region.
+    pub guid: Uuid,
+    /// Absolute offset of the region, a multiple of 1 MiB.
+    pub file_offset: u64,
+    /// Size of the region in bytes, a multiple of 1 MiB.
+    pub length: u32,
+    /// Whether the file cannot be opened by implementations that do not know the region.
+    pub required: bool,
+}
+
+/// The region table at offset 192 KiB of a VHDX file, and its copy at 256 KiB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionTable {
+    /// The CRC-32C stored in the table. [`RegionTable::to_bytes`] recomputes it.
+    pub checksum: u32,
+    pub entries: Vec<RegionTableEntry>,
+}
+
+impl RegionTable {
+    /// Parses the region table in `bytes`, which was read from file offset `offset`.
+    ///
+    /// # Errors
+    /// If the signature, checksum or entry count is invalid, or a region is not aligned to
+    /// 1 MiB.
+    pub fn parse(bytes: &[u8; REGION_TABLE_SIZE], offset: u64) -> Result<Self, ParseError> {
+        let field_offset = |field: usize| offset + field as u64;
+
+        if &bytes[..4] != SIGNATURE {
+            return Err(ParseError::mismatch(
+                Structure::RegionTable,
+                "signature",
+                offset,
+                "\"regi\"",
+                format!("{:?}", String::from_utf8_lossy(&bytes[..4])),
+            ));
+        }
+
+        let stored_checksum = le_u32(bytes, CHECKSUM);
+        let computed_checksum = checksum(bytes, CHECKSUM);
+        if stored_checksum != computed_checksum {
+            return Err(ParseError::mismatch(
+                Structure::RegionTable,
+                "checksum",
+                field_offset(CHECKSUM),
+                format!("{computed_checksum:#010x}"),
+                format!("{stored_checksum:#010x}"),
+            ));
+        }
+
+        let entry_count = le_u32(bytes, ENTRY_COUNT);
+        if entry_count > MAX_ENTRIES {
+            return Err(ParseError::mismatch(
+                Structure::RegionTable,
+                "entry count",
+                field_offset(ENTRY_COUNT),
+                format!("at most {MAX_ENTRIES}"),
+                entry_count,
+            ));
+        }
+
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        for index in 0..entry_count as usize {
+            let entry = ENTRIES + index * ENTRY_SIZE;
+            let file_offset = le_u64(bytes, entry + 16);
+            let length = le_u32(bytes, entry + 24);
+            if file_offset < MIB || !file_offset.is_multiple_of(MIB) {
+                return Err(ParseError::mismatch(
+                    Structure::RegionTable,
+                    "file offset",
+                    field_offset(entry + 16),
+                    "a non-zero multiple of 1 MiB",
+                    file_offset,
+                ));
+            }
+            if !u64::from(length).is_multiple_of(MIB) {
+                return Err(ParseError::mismatch(
+                    Structure::RegionTable,
+                    "length",
+                    field_offset(entry + 24),
+                    "a multiple of 1 MiB",
+                    length,
+                ));
+            }
+
+            entries.push(RegionTableEntry {
+                guid: guid(bytes, entry),
+                file_offset,
+                length,
+                required: le_u32(bytes, entry + 28) & 1 != 0,
+            });
+        }
+
+        Ok(RegionTable {
+            checksum: stored_checksum,
+            entries,
+        })
+    }
+
+    /// Returns the entry of the region identified by `guid`.
+    pub fn region(&self, guid: Uuid) -> Option<&RegionTableEntry> {
+        self.entries.iter().find(|entry| entry.guid == guid)
+    }
+
+    /// Serializes the region table, computing a fresh checksum. The stored
+    /// [`RegionTable::checksum`] is ignored.
+    pub fn to_bytes(&self) -> Box<[u8; REGION_TABLE_SIZE]> {
+        let mut bytes = Box::new([0; REGION_TABLE_SIZE]);
+        bytes[..4].copy_from_slice(SIGNATURE);
+        bytes[ENTRY_COUNT..ENTRY_COUNT + 4]
+            .copy_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (index, region) in self.entries.iter().enumerate() {
+            let entry = ENTRIES + index * ENTRY_SIZE;
+            bytes[entry..entry + 16].copy_from_slice(&region.guid.to_bytes_le());
+            bytes[entry + 16..entry + 24].copy_from_slice(&region.file_offset.to_le_bytes());
+            bytes[entry + 24..entry + 28].copy_from_slice(&region.length.to_le_bytes());
+            bytes[entry + 28..entry + 32]
+                .copy_from_slice(&u32::from(region.required).to_le_bytes());
+        }
+
+        let checksum = checksum(&bytes[..], CHECKSUM);
+        bytes[CHECKSUM..CHECKSUM + 4].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> RegionTable {
+        RegionTable {
+            checksum: 0,
+            entries: vec![
+                RegionTableEntry {
+                    guid: BAT_REGION,
+                    file_offset: 3 * MIB,
+                    length: MIB as u32,
+                    required: true,
+                },
+                RegionTableEntry {
+                    guid: METADATA_REGION,
+                    file_offset: 2 * MIB,
+                    length: MIB as u32,
+                    required: true,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = table().to_bytes();
+        let parsed = RegionTable::parse(&bytes, REGION_TABLE_OFFSETS[0]).unwrap();
+        assert_eq!(parsed.checksum, checksum(&bytes[..], CHECKSUM));
+        assert_eq!(parsed.region(METADATA_REGION).unwrap().file_offset, 2 * MIB);
+        assert_eq!(
+            RegionTable {
+                checksum: 0,
+                ..parsed
+            },
+            table()
+        );
+        // the BAT GUID as it appears in files written by Windows
+        assert_eq!(&bytes[16..20], &[0x66, 0x77, 0xc2, 0x2d]);
+    }
+
+    #[test]
+    fn corrupt_table() {
+        let mut bytes = table().to_bytes();
+        bytes[ENTRIES + 20] ^= 1;
+        let error = RegionTable::parse(&bytes, REGION_TABLE_OFFSETS[1]).unwrap_err();
+        assert_eq!(error.structure(), Structure::RegionTable);
+        assert_eq!(error.field(), "checksum");
+        assert_eq!(error.offset(), REGION_TABLE_OFFSETS[1] + 4);
+    }
+
+    #[test]
+    fn unaligned_region() {
+        let mut table = table();
+        table.entries[0].file_offset += 4096;
+        let error = RegionTable::parse(&table.to_bytes(), 0).unwrap_err();
+        assert_eq!(error.field(), "file offset");
+        assert_eq!(error.offset(), 32);
+    }
+}